@@ -1,39 +1,163 @@
+use crate::document;
+use crate::errors::RegistryError;
+use crate::migrate::CONFIG_VERSION;
+use crate::project::PROJECT_DIR;
+use crate::repo::RepoPath;
 use miette::{IntoDiagnostic, Result, SourceSpan};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, TableLike};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Configuration shared by every user of the machine
+const SYSTEM_CONFIG: &str = "/etc/cpr/config.toml";
+
+/// Prefix of environment variables overriding configuration values
+const ENV_PREFIX: &str = "CPR_";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct BaseURL {
     /// The URL format for the git server
     pub url: String,
+    /// URL formats of mirrors, tried in order before `url`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+    /// User available as `{{ user }}` in the URL format, defaults to the current user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Private SSH key used to authenticate, tried before the ssh-agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_key: Option<PathBuf>,
+    /// Environment variable holding an access token for HTTPS authentication
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_env: Option<String>,
+    /// HTTP proxy used to reach the service (ex. http://proxy.example.com:8080)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Clone only the given number of commits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<i32>,
+    /// Clone submodules of the template, recursively
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub submodules: bool,
+    /// CA bundle used to verify the certificate of the service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+    /// Skip certificate verification, for self-signed servers
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub insecure: bool,
+    /// Ref checked out when the repository path doesn't specify one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_ref: Option<String>,
+    /// Keys this version of cpr doesn't know, kept when writing
+    #[serde(flatten)]
+    pub extra: toml::Table,
+}
+
+impl BaseURL {
+    /// Render the URL formats of the mirrors and of the service for a repository, in the order
+    /// they should be tried
+    pub fn render(&self, repo_path: &RepoPath) -> Result<Vec<String>> {
+        self.mirrors
+            .iter()
+            .chain(std::iter::once(&self.url))
+            .map(|url| self.render_url(url, repo_path))
+            .collect()
+    }
+
+    /// Render a URL format for a repository
+    ///
+    /// Available variables are `{{ repo }}`, `{{ owner }}`, `{{ name }}`, `{{ group }}`,
+    /// `{{ ref }}` and `{{ user }}`.
+    fn render_url(&self, url: &str, repo_path: &RepoPath) -> Result<String> {
+        let user = self
+            .user
+            .clone()
+            .or_else(|| std::env::var("USER").ok())
+            .or_else(|| std::env::var("USERNAME").ok())
+            .unwrap_or_default();
+        let mut context = BTreeMap::<&str, upon::Value>::new();
+        context.insert("repo", repo_path.repo.clone().into());
+        context.insert("owner", repo_path.owner().into());
+        context.insert("name", repo_path.name().into());
+        context.insert("group", repo_path.group().into());
+        context.insert(
+            "ref",
+            repo_path.reference.clone().unwrap_or_default().into(),
+        );
+        context.insert("user", user.into());
+
+        let engine = upon::Engine::new();
+        engine
+            .compile(url)
+            .and_then(|template| template.render(&engine, context).to_string())
+            .map_err(|e| ConfigErrorKind::InvalidServiceUrl(format!("{:#}", e)))
+            .into_diagnostic()
+    }
+
+    /// Check that the URL format renders, so mistakes surface when the service is added
+    pub fn validate(&self) -> Result<()> {
+        self.render(&RepoPath::parse("group/subgroup/name@main"))
+            .map(|_| ())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Registry {
+    /// Path to a local TOML or JSON index file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Repository path of a git repository containing the index file (ex. gh:org/cpr-index)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    /// Index file within the repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    /// Keys this version of cpr doesn't know, kept when writing
+    #[serde(flatten)]
+    pub extra: toml::Table,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
+    /// Version of the configuration format, files without one predate versioning
+    #[serde(default)]
+    pub version: i64,
     /// Map of prefixes to git server URL formats
-    pub services: HashMap<String, BaseURL>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub services: BTreeMap<String, BaseURL>,
     /// Default prefix when one is not specified at the command line
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub default_service: String,
+    /// Template registry index files used to look up templates by name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub registries: Vec<Registry>,
+    /// Map of short names to full repository paths
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, String>,
+    /// Keys this version of cpr doesn't know, kept when writing
+    #[serde(flatten)]
+    pub extra: toml::Table,
 }
 
 // Adapted from https://github.com/zkat/miette/blob/main/examples/serde_json.rs, Thank you!
 #[derive(Debug, thiserror::Error, miette::Diagnostic)]
 #[error("malformed config provided")]
 pub struct ConfigError {
-    cause: toml::de::Error,
+    message: String,
     #[source_code]
     input: String,
-    #[label("{cause}")]
+    #[label("{message}")]
     location: SourceSpan,
 }
 
 impl ConfigError {
     pub fn from_serde_error(input: impl Into<String>, cause: toml::de::Error) -> Self {
         let input = input.into();
-        let location = SourceSpan::from(cause.span().unwrap());
+        let location = SourceSpan::from(cause.span().unwrap_or_default());
         Self {
-            cause,
+            message: cause.message().to_string(),
             input,
             location,
         }
@@ -44,18 +168,53 @@ impl ConfigError {
 pub enum ConfigErrorKind {
     #[error("Service not found")]
     ServiceNotFound,
+
+    #[error("Alias not found")]
+    AliasNotFound,
+
+    #[error("Invalid service URL: {0}")]
+    InvalidServiceUrl(String),
+
+    #[error("Configuration key `{0}` not found")]
+    KeyNotFound(String),
+
+    #[error("Configuration key `{0}` is not a table")]
+    NotATable(String),
+
+    #[error("No default service set")]
+    MissingDefaultService,
+
+    #[error("Default service `{0}` is not defined in `services`")]
+    UnknownDefaultService(String),
+
+    #[error("Service `{0}`: {1}")]
+    InvalidService(String, String),
+
+    #[error("Failed to run editor `{0}`")]
+    EditorFail(String),
+
+    #[error("Invalid configuration version `{0}`, expected a positive integer")]
+    InvalidVersion(String),
+
+    #[error("Configuration version {0} is newer than the supported version {1}, update cpr")]
+    UnsupportedVersion(i64, i64),
 }
 
 impl Config {
     pub fn init(path: &Path) -> Result<()> {
         let mut config = Config {
-            services: HashMap::new(),
+            version: CONFIG_VERSION,
+            services: BTreeMap::new(),
             default_service: "gh".to_string(),
+            registries: Vec::new(),
+            aliases: BTreeMap::new(),
+            extra: toml::Table::new(),
         };
         config.services.insert(
             "gh".to_string(),
             BaseURL {
                 url: "https://github.com/{{ repo }}.git".to_string(),
+                ..Default::default()
             },
         );
         let toml = toml::to_string(&config).into_diagnostic()?;
@@ -67,35 +226,69 @@ impl Config {
             std::fs::create_dir_all(parent).into_diagnostic()?;
         }
 
-        std::fs::write(path, toml).into_diagnostic()
+        document::write_atomic(path, &toml)
     }
 
-    pub fn from_file(path: &Path) -> Result<Self> {
-        log::debug!("reading config from file: {:?}", path);
-        let input = std::fs::read_to_string(path).into_diagnostic()?;
-        toml::from_str(&input)
-            .map_err(|e| ConfigError::from_serde_error(input, e))
+    /// Check what deserializing can't: the default service exists, service URLs render and
+    /// registries have a source
+    pub fn validate(&self) -> Result<()> {
+        if self.default_service.is_empty() {
+            return Err(ConfigErrorKind::MissingDefaultService).into_diagnostic();
+        }
+        if !self.services.contains_key(&self.default_service) {
+            return Err(ConfigErrorKind::UnknownDefaultService(
+                self.default_service.clone(),
+            ))
+            .into_diagnostic();
+        }
+        for (prefix, service) in &self.services {
+            service
+                .validate()
+                .map_err(|e| ConfigErrorKind::InvalidService(prefix.clone(), e.to_string()))
+                .into_diagnostic()?;
+        }
+        if self
+            .registries
+            .iter()
+            .any(|r| r.path.is_none() && r.repo.is_none())
+        {
+            return Err(RegistryError::MissingSource).into_diagnostic();
+        }
+        Ok(())
+    }
+
+    /// Service for a prefix, falling back to the default service
+    pub fn service(&self, prefix: &str) -> Result<&BaseURL> {
+        if let Some(service) = self.services.get(prefix) {
+            return Ok(service);
+        }
+        log::warn!("prefix not found, using default: {}", self.default_service);
+        self.services
+            .get(&self.default_service)
+            .ok_or_else(|| ConfigErrorKind::UnknownDefaultService(self.default_service.clone()))
             .into_diagnostic()
     }
 
-    pub fn clone_url(&self, prefix: &str, repo_path: &str) -> String {
+    /// URLs to clone a repository from, mirrors first
+    pub fn clone_urls(&self, prefix: &str, repo_path: &RepoPath) -> Result<Vec<String>> {
         log::debug!(
             "querying config for `prefix:path` -> {}:{}",
             prefix,
-            repo_path
+            repo_path.repo
         );
-        let base_url = self.services.get(prefix).unwrap_or_else(|| {
-            log::warn!("prefix not found, using default: {}", self.default_service);
-            self.services
-                .get(&self.default_service)
-                .expect("No default prefix found")
-        });
+        let base_url = self.service(prefix)?;
         log::debug!("using base URL: {}", base_url.url);
-        base_url.url.replace("{{ repo }}", repo_path)
+        base_url.render(repo_path)
     }
 
-    pub fn add_service(&mut self, prefix: String, url: String) -> Result<()> {
-        self.services.insert(prefix, BaseURL { url });
+    pub fn add_service(&mut self, prefix: String, url: String, user: Option<String>) -> Result<()> {
+        let base_url = BaseURL {
+            url,
+            user,
+            ..Default::default()
+        };
+        base_url.validate()?;
+        self.services.insert(prefix, base_url);
         Ok(())
     }
 
@@ -116,8 +309,579 @@ impl Config {
         }
     }
 
-    pub fn write(&self, path: &Path) -> Result<()> {
-        let toml = toml::to_string(self).into_diagnostic()?;
-        std::fs::write(path, toml).into_diagnostic()
+    /// Expand an alias (ex. `lib` or `lib@v2`) to the repository path it stands for,
+    /// anything else is returned unchanged
+    pub fn resolve_alias(&self, repo_path: &str) -> String {
+        let path = RepoPath::parse(repo_path);
+        if path.prefix.is_some() || path.subdir.is_some() {
+            return repo_path.to_string();
+        }
+        match self.aliases.get(&path.repo) {
+            Some(target) => {
+                let mut target = RepoPath::parse(target);
+                if path.reference.is_some() {
+                    target.reference = path.reference;
+                }
+                log::debug!("resolved alias `{}` to `{}`", repo_path, target);
+                target.to_string()
+            }
+            None => repo_path.to_string(),
+        }
+    }
+
+    pub fn add_alias(&mut self, name: String, repo_path: String) -> Result<()> {
+        self.aliases.insert(name, repo_path);
+        Ok(())
+    }
+
+    pub fn remove_alias(&mut self, name: &str) -> Result<()> {
+        if self.aliases.remove(name).is_none() {
+            Err::<(), ConfigErrorKind>(ConfigErrorKind::AliasNotFound).into_diagnostic()
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// User configuration file, `$XDG_CONFIG_HOME/cpr/config.toml` when set, `~/.cpr/config.toml`
+/// otherwise
+///
+/// An existing `~/.cpr/config.toml` is kept until the XDG one is created, so setting
+/// `XDG_CONFIG_HOME` doesn't hide the services and aliases configured before.
+pub fn user_config_path() -> PathBuf {
+    let xdg = std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty());
+    user_config_in(xdg.as_deref().map(Path::new), dirs::home_dir().as_deref())
+        .expect("Could not determine home directory")
+}
+
+fn user_config_in(xdg_dir: Option<&Path>, home_dir: Option<&Path>) -> Option<PathBuf> {
+    let home = home_dir.map(home_config_path);
+    let Some(xdg_dir) = xdg_dir else {
+        return home;
+    };
+    let xdg = xdg_dir.join("cpr").join("config.toml");
+    match home {
+        Some(home) if home.is_file() && !xdg.is_file() => Some(home),
+        _ => Some(xdg),
+    }
+}
+
+/// `~/.cpr/config.toml`, the user configuration when `XDG_CONFIG_HOME` isn't used
+fn home_config_path(home_dir: &Path) -> PathBuf {
+    home_dir.join(PROJECT_DIR).join("config.toml")
+}
+
+/// Closest `.cpr/config.toml` in the current directory or its parents
+fn project_config_path(user_path: &Path) -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    project_config_in(&cwd, user_path, dirs::home_dir().as_deref())
+}
+
+fn project_config_in(cwd: &Path, user_path: &Path, home_dir: Option<&Path>) -> Option<PathBuf> {
+    // `~/.cpr` holds user configuration whichever file is in use, don't read it as a project
+    let mut skipped = vec![user_path.to_path_buf()];
+    skipped.extend(home_dir.map(home_config_path));
+    let skipped = skipped
+        .iter()
+        .filter_map(|path| path.canonicalize().ok())
+        .collect::<Vec<_>>();
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_DIR).join("config.toml"))
+        .filter(|path| path.is_file())
+        .find(|path| {
+            path.canonicalize()
+                .map_or(true, |path| !skipped.contains(&path))
+        })
+}
+
+/// Layer a configuration value was set by
+#[derive(Debug, Clone)]
+pub enum Origin {
+    System(PathBuf),
+    User(PathBuf),
+    Project(PathBuf),
+    Env(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::System(path) => write!(f, "system: {}", path.display()),
+            Origin::User(path) => write!(f, "user: {}", path.display()),
+            Origin::Project(path) => write!(f, "project: {}", path.display()),
+            Origin::Env(name) => write!(f, "env: {}", name),
+        }
+    }
+}
+
+/// Configuration merged from the system, user and project files then `CPR_*` environment
+/// variables, later layers overriding earlier ones key by key
+#[derive(Debug, Default)]
+pub struct ConfigLayers {
+    /// Values of each layer, in merge order
+    layers: Vec<(Origin, toml::Table)>,
+    /// Merged values of every layer
+    pub table: toml::Table,
+    /// Layer each value was set by, keyed by dotted path (ex. `services.gh.url`)
+    pub origins: BTreeMap<String, Origin>,
+}
+
+impl ConfigLayers {
+    pub fn load(user_path: &Path) -> Result<Self> {
+        let mut layers = Vec::new();
+        let system_path = PathBuf::from(SYSTEM_CONFIG);
+        if system_path.is_file() {
+            layers.push((
+                Origin::System(system_path.clone()),
+                read_table(&system_path)?,
+            ));
+        }
+        layers.push((
+            Origin::User(user_path.to_path_buf()),
+            read_table(user_path)?,
+        ));
+        if let Some(project_path) = project_config_path(user_path) {
+            layers.push((
+                Origin::Project(project_path.clone()),
+                read_table(&project_path)?,
+            ));
+        }
+
+        let mut vars = std::env::vars()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect::<Vec<_>>();
+        vars.sort();
+        for (name, value) in vars {
+            if let Some(layer) = env_layer(&name, &value) {
+                layers.push((Origin::Env(name), layer));
+            }
+        }
+        Ok(Self::from_layers(layers))
+    }
+
+    fn from_layers(layers: Vec<(Origin, toml::Table)>) -> Self {
+        let mut table = toml::Table::new();
+        let mut origins = BTreeMap::new();
+        for (origin, layer) in &layers {
+            merge(&mut table, layer.clone(), "", origin, &mut origins);
+        }
+        Self {
+            layers,
+            table,
+            origins,
+        }
+    }
+
+    /// Deserialize the merged configuration
+    pub fn config(&self) -> Result<Config> {
+        let input = toml::to_string(&self.table).into_diagnostic()?;
+        toml::from_str(&input)
+            .map_err(|e| ConfigError::from_serde_error(input, e).into())
+    }
+
+    /// Deserialize the merged configuration and check that it makes sense
+    pub fn validate(&self) -> Result<Config> {
+        let config = self.config()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Every merged value with its dotted path, in path order
+    pub fn values(&self) -> Vec<(String, &toml::Value)> {
+        let mut values = Vec::new();
+        collect_values(&self.table, "", &mut values);
+        values.sort_by(|(a, _), (b, _)| a.cmp(b));
+        values
+    }
+
+    /// Merged value at a dotted path (ex. `services.gh.url`)
+    pub fn get(&self, key: &str) -> Result<&toml::Value> {
+        let mut keys = key.split('.');
+        let first = self.table.get(keys.next().unwrap_or_default());
+        keys.fold(first, |value, key| value.and_then(|v| v.get(key)))
+            .ok_or_else(|| ConfigErrorKind::KeyNotFound(key.to_string()))
+            .into_diagnostic()
+    }
+
+    pub fn user_path(&self) -> &Path {
+        self.layers
+            .iter()
+            .find_map(|(origin, _)| match origin {
+                Origin::User(path) => Some(path.as_path()),
+                _ => None,
+            })
+            .expect("the user layer is always loaded")
+    }
+
+    /// Set a value in the user configuration, the value is parsed as TOML when possible
+    pub fn set(&self, key: &str, value: &str) -> Result<()> {
+        let layers = self.edit_user(|doc| {
+            let (parents, last) = split_key(key);
+            let mut table = doc.as_table_mut() as &mut dyn TableLike;
+            for parent in parents {
+                table = table
+                    .entry(parent)
+                    .or_insert_with(|| {
+                        let mut table = toml_edit::Table::new();
+                        table.set_implicit(true);
+                        Item::Table(table)
+                    })
+                    .as_table_like_mut()
+                    .ok_or_else(|| ConfigErrorKind::NotATable(parent.to_string()))
+                    .into_diagnostic()?;
+            }
+            let value = value
+                .parse::<toml_edit::Value>()
+                .unwrap_or_else(|_| value.into());
+            table.insert(last, toml_edit::value(value));
+            Ok(())
+        })?;
+        if let Some(origin) = layers
+            .origins
+            .get(key)
+            .filter(|origin| !matches!(origin, Origin::User(_)))
+        {
+            eprintln!("! WARN: `{}` is overridden by {}", key, origin);
+        }
+        Ok(())
+    }
+
+    /// Remove a value from the user configuration
+    pub fn unset(&self, key: &str) -> Result<()> {
+        self.edit_user(|doc| {
+            let (parents, last) = split_key(key);
+            parents
+                .into_iter()
+                .try_fold(doc.as_table_mut() as &mut dyn TableLike, |table, parent| {
+                    table.get_mut(parent).and_then(|v| v.as_table_like_mut())
+                })
+                .and_then(|table| table.remove(last))
+                .map(|_| ())
+                .ok_or_else(|| ConfigErrorKind::KeyNotFound(key.to_string()))
+                .into_diagnostic()
+        })
+        .map(|_| ())
+    }
+
+    /// Apply changes to the deserialized user configuration, keeping comments and unknown keys
+    ///
+    /// Like `set`, the file is only written if the result merged with the other layers is valid.
+    pub fn edit(&self, f: impl FnOnce(&mut Config) -> Result<()>) -> Result<()> {
+        self.edit_user(|doc| {
+            let mut config: Config = document::parse(doc)?;
+            f(&mut config)?;
+            document::update(doc, &config)
+        })
+        .map(|_| ())
+    }
+
+    /// Apply a change to the user configuration, and write it only if the result is valid
+    fn edit_user(&self, f: impl FnOnce(&mut DocumentMut) -> Result<()>) -> Result<Self> {
+        let path = self.user_path().to_path_buf();
+        let (mut doc, _) = document::read(&path)?;
+        f(&mut doc)?;
+
+        document::parse::<Config>(&doc)?;
+        let table: toml::Table = document::parse(&doc)?;
+        let layers = self
+            .layers
+            .iter()
+            .map(|(origin, layer)| match origin {
+                Origin::User(_) => (origin.clone(), table.clone()),
+                _ => (origin.clone(), layer.clone()),
+            })
+            .collect();
+        let layers = Self::from_layers(layers);
+        layers.validate()?;
+
+        document::write(&path, &doc)?;
+        Ok(layers)
+    }
+}
+
+/// Check a user configuration file merged with the other layers
+pub fn validate_file(path: &Path) -> Result<Config> {
+    ConfigLayers::load(path)?.validate()
+}
+
+/// Read a layer migrated to the current version, each file is checked on its own so errors
+/// point into it
+fn read_table(path: &Path) -> Result<toml::Table> {
+    let (doc, _) = document::read(path)?;
+    document::parse::<Config>(&doc)?;
+    document::parse(&doc)
+}
+
+/// Parse a value given on the command line or in the environment as TOML (ex. `1`, `true`,
+/// `["a"]`), falling back to a string
+fn parse_value(value: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut t| t.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+/// Split a dotted path into its parent tables and last key
+fn split_key(key: &str) -> (Vec<&str>, &str) {
+    let mut keys = key.split('.').collect::<Vec<_>>();
+    let last = keys.pop().unwrap_or_default();
+    (keys, last)
+}
+
+/// Layer for a variable such as `CPR_DEFAULT_SERVICE=gl` or `CPR_SERVICES__GH__DEPTH=1`,
+/// where `__` separates nested keys
+fn env_layer(name: &str, value: &str) -> Option<toml::Table> {
+    let path = name[ENV_PREFIX.len()..].to_lowercase();
+    if path.is_empty() {
+        return None;
+    }
+    log::debug!("reading config value from environment: {}", name);
+    let mut value = parse_value(value);
+    for key in path.rsplit("__") {
+        value = toml::Value::Table(toml::Table::from_iter([(key.to_string(), value)]));
+    }
+    match value {
+        toml::Value::Table(layer) => Some(layer),
+        _ => None,
+    }
+}
+
+fn join_path(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+/// Merge tables recursively, any other value replaces the previous one
+fn merge(
+    target: &mut toml::Table,
+    layer: toml::Table,
+    prefix: &str,
+    origin: &Origin,
+    origins: &mut BTreeMap<String, Origin>,
+) {
+    for (key, value) in layer {
+        let path = join_path(prefix, &key);
+        match (target.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(table)) => {
+                merge(existing, table, &path, origin, origins);
+            }
+            (_, value) => {
+                origins.retain(|k, _| *k != path && !k.starts_with(&format!("{}.", path)));
+                let layer = toml::Table::from_iter([(key, value)]);
+                let mut values = Vec::new();
+                collect_values(&layer, prefix, &mut values);
+                for (path, _) in values {
+                    origins.insert(path, origin.clone());
+                }
+                target.extend(layer);
+            }
+        }
+    }
+}
+
+fn collect_values<'a>(
+    table: &'a toml::Table,
+    prefix: &str,
+    values: &mut Vec<(String, &'a toml::Value)>,
+) {
+    for (key, value) in table {
+        let path = join_path(prefix, key);
+        match value {
+            toml::Value::Table(table) => collect_values(table, &path, values),
+            value => values.push((path, value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(path: &Path) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "").unwrap();
+    }
+
+    #[test]
+    fn user_config_defaults_to_home_without_xdg() {
+        let home = tempfile::tempdir().unwrap();
+        assert_eq!(
+            user_config_in(None, Some(home.path())),
+            Some(home.path().join(".cpr/config.toml"))
+        );
+    }
+
+    #[test]
+    fn user_config_keeps_existing_home_file() {
+        let home = tempfile::tempdir().unwrap();
+        let xdg = tempfile::tempdir().unwrap();
+        touch(&home.path().join(".cpr/config.toml"));
+        assert_eq!(
+            user_config_in(Some(xdg.path()), Some(home.path())),
+            Some(home.path().join(".cpr/config.toml"))
+        );
+
+        touch(&xdg.path().join("cpr/config.toml"));
+        assert_eq!(
+            user_config_in(Some(xdg.path()), Some(home.path())),
+            Some(xdg.path().join("cpr/config.toml"))
+        );
+    }
+
+    #[test]
+    fn user_config_uses_xdg_when_nothing_exists() {
+        let home = tempfile::tempdir().unwrap();
+        let xdg = tempfile::tempdir().unwrap();
+        assert_eq!(
+            user_config_in(Some(xdg.path()), Some(home.path())),
+            Some(xdg.path().join("cpr/config.toml"))
+        );
+    }
+
+    #[test]
+    fn project_config_skips_home_config() {
+        let home = tempfile::tempdir().unwrap();
+        let xdg = tempfile::tempdir().unwrap();
+        let project = home.path().join("work/app");
+        touch(&home.path().join(".cpr/config.toml"));
+        std::fs::create_dir_all(&project).unwrap();
+        let user_path = xdg.path().join("cpr/config.toml");
+        assert_eq!(
+            project_config_in(&project, &user_path, Some(home.path())),
+            None
+        );
+
+        touch(&project.join(".cpr/config.toml"));
+        assert_eq!(
+            project_config_in(&project, &user_path, Some(home.path())),
+            Some(project.join(".cpr/config.toml"))
+        );
+    }
+
+    #[test]
+    fn edit_keeps_the_file_when_the_result_is_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        Config::init(&path).unwrap();
+        let before = std::fs::read_to_string(&path).unwrap();
+        let layers = ConfigLayers::from_layers(vec![(
+            Origin::User(path.clone()),
+            read_table(&path).unwrap(),
+        )]);
+
+        assert!(layers.edit(|c| c.remove_service("gh")).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn service_without_default_is_an_error() {
+        let config: Config = toml::from_str(
+            r#"
+            default_service = "gh"
+
+            [services.gl]
+            url = "https://gitlab.com/{{ repo }}.git"
+            "#,
+        )
+        .unwrap();
+        assert!(config.service("gl").is_ok());
+        assert!(config.service("gh").is_err());
+    }
+
+    fn table(toml: &str) -> toml::Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn env_layer_nests_keys_split_by_double_underscores() {
+        assert_eq!(
+            env_layer("CPR_DEFAULT_SERVICE", "gl"),
+            Some(table(r#"default_service = "gl""#))
+        );
+        assert_eq!(
+            env_layer("CPR_SERVICES__GH__DEPTH", "1"),
+            Some(table("services.gh.depth = 1"))
+        );
+        assert_eq!(
+            env_layer("CPR_SERVICES__GH__INSECURE", "true"),
+            Some(table("services.gh.insecure = true"))
+        );
+        assert_eq!(env_layer("CPR_", "gl"), None);
+    }
+
+    #[test]
+    fn merge_records_the_layer_of_each_value() {
+        let layers = ConfigLayers::from_layers(vec![
+            (
+                Origin::System(PathBuf::from("/etc/cpr/config.toml")),
+                table(
+                    r#"
+                    default_service = "gh"
+                    services.gh.url = "https://github.com/{{ repo }}.git"
+                    services.gh.depth = 1
+                    "#,
+                ),
+            ),
+            (
+                Origin::User(PathBuf::from("config.toml")),
+                table(r#"default_service = "gl""#),
+            ),
+            (
+                Origin::Env("CPR_SERVICES__GH__DEPTH".to_string()),
+                env_layer("CPR_SERVICES__GH__DEPTH", "0").unwrap(),
+            ),
+        ]);
+
+        assert_eq!(
+            layers.table,
+            table(
+                r#"
+                default_service = "gl"
+                services.gh.url = "https://github.com/{{ repo }}.git"
+                services.gh.depth = 0
+                "#,
+            )
+        );
+        let origins = layers
+            .origins
+            .iter()
+            .map(|(path, origin)| (path.as_str(), origin.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            origins,
+            [
+                ("default_service", "user: config.toml".to_string()),
+                (
+                    "services.gh.depth",
+                    "env: CPR_SERVICES__GH__DEPTH".to_string()
+                ),
+                (
+                    "services.gh.url",
+                    "system: /etc/cpr/config.toml".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_forgets_the_origins_of_replaced_tables() {
+        let layers = ConfigLayers::from_layers(vec![
+            (
+                Origin::User(PathBuf::from("config.toml")),
+                table(r#"services.gh.url = "https://github.com/{{ repo }}.git""#),
+            ),
+            (
+                Origin::Project(PathBuf::from(".cpr/config.toml")),
+                table(r#"services.gh = "replaced""#),
+            ),
+        ]);
+        assert_eq!(layers.origins.keys().collect::<Vec<_>>(), ["services.gh"]);
+        assert_eq!(
+            layers.origins["services.gh"].to_string(),
+            "project: .cpr/config.toml"
+        );
     }
 }
@@ -0,0 +1,141 @@
+use crate::config::ConfigError;
+use crate::migrate;
+use miette::{IntoDiagnostic, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::Write;
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, TableLike};
+
+/// Read a configuration file as an editable document migrated to the current version, along
+/// with a description of each migration change
+pub fn read(path: &Path) -> Result<(DocumentMut, Vec<String>)> {
+    log::debug!("reading config document from file: {:?}", path);
+    let input = std::fs::read_to_string(path).into_diagnostic()?;
+    // toml errors carry the spans reported by `ConfigError`
+    toml::from_str::<toml::Table>(&input).map_err(|e| ConfigError::from_serde_error(&input, e))?;
+    let mut doc = input.parse::<DocumentMut>().into_diagnostic()?;
+    let changes = migrate::migrate(&mut doc)?;
+    Ok((doc, changes))
+}
+
+/// Deserialize a document, errors point into its text
+pub fn parse<T: DeserializeOwned>(doc: &DocumentMut) -> Result<T> {
+    let input = doc.to_string();
+    toml::from_str(&input).map_err(|e| ConfigError::from_serde_error(input, e).into())
+}
+
+/// Update a document to hold the serialized value, comments, formatting and the order of
+/// unchanged keys are left alone
+pub fn update<T: Serialize>(doc: &mut DocumentMut, value: &T) -> Result<()> {
+    let table = toml::Table::try_from(value).into_diagnostic()?;
+    sync_table(doc.as_table_mut(), &table, 0);
+    Ok(())
+}
+
+pub fn write(path: &Path, doc: &DocumentMut) -> Result<()> {
+    log::debug!("writing config document to file: {:?}", path);
+    write_atomic(path, &doc.to_string())
+}
+
+/// Write through a temporary file renamed over the destination, so an interrupted write never
+/// leaves a truncated file behind
+pub fn write_atomic(path: &Path, contents: &str) -> Result<()> {
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+    let mut file = tempfile::NamedTempFile::new_in(dir.unwrap_or(Path::new(".")))
+        .into_diagnostic()?;
+    file.write_all(contents.as_bytes()).into_diagnostic()?;
+    // temporary files are private, keep the permissions of the file being replaced
+    if let Ok(metadata) = std::fs::metadata(path) {
+        file.as_file()
+            .set_permissions(metadata.permissions())
+            .into_diagnostic()?;
+    }
+    file.persist(path).into_diagnostic()?;
+    Ok(())
+}
+
+fn sync_table(doc: &mut dyn TableLike, table: &toml::Table, position: usize) {
+    let removed = doc
+        .iter()
+        .map(|(key, _)| key.to_string())
+        .filter(|key| !table.contains_key(key))
+        .collect::<Vec<_>>();
+    for key in removed {
+        doc.remove(&key);
+    }
+    // new sections go right after the last section of their parent
+    let position = last_position(doc).unwrap_or(position);
+    for (key, value) in table {
+        match (doc.get_mut(key), value) {
+            (Some(item), toml::Value::Table(table)) if item.is_table_like() => {
+                sync_table(item.as_table_like_mut().unwrap(), table, position);
+            }
+            (Some(item), value) if item_value(key, item).as_ref() == Some(value) => {}
+            (Some(Item::Value(old)), value) => {
+                // keep the comments around a changed value
+                let decor = old.decor().clone();
+                let mut item = to_item(key, value);
+                if let Some(new) = item.as_value_mut() {
+                    *new.decor_mut() = decor;
+                }
+                doc.insert(key, item);
+            }
+            (_, value) => {
+                let mut item = to_item(key, value);
+                place(&mut item, position);
+                doc.insert(key, item);
+            }
+        }
+    }
+}
+
+/// Position of the last section within a table
+fn last_position(table: &dyn TableLike) -> Option<usize> {
+    table
+        .iter()
+        .flat_map(|(_, item)| match item {
+            Item::Table(table) => vec![table],
+            Item::ArrayOfTables(array) => array.iter().collect(),
+            _ => Vec::new(),
+        })
+        .flat_map(|table| table.position().into_iter().chain(last_position(table)))
+        .max()
+}
+
+/// Place a new section and its children at a position, ties keep document order
+fn place(item: &mut Item, position: usize) {
+    let tables = match item {
+        Item::Table(table) => vec![table],
+        Item::ArrayOfTables(array) => array.iter_mut().collect(),
+        _ => Vec::new(),
+    };
+    for table in tables {
+        table.set_position(position);
+        // separate new sections from what precedes them
+        if table.decor().prefix().map_or(true, |p| p.as_str() == Some("")) {
+            table.decor_mut().set_prefix("\n");
+        }
+        for (_, child) in table.iter_mut() {
+            place(child, position);
+        }
+    }
+}
+
+/// Value of a document item, converted through a document holding only that item
+fn item_value(key: &str, item: &Item) -> Option<toml::Value> {
+    let mut doc = DocumentMut::new();
+    doc.insert(key, item.clone());
+    toml::from_str::<toml::Table>(&doc.to_string())
+        .ok()?
+        .remove(key)
+}
+
+/// Item formatted the way `toml` writes it, with tables and arrays of tables as sections
+fn to_item(key: &str, value: &toml::Value) -> Item {
+    let table = toml::Table::from_iter([(key.to_string(), value.clone())]);
+    toml::to_string(&table)
+        .ok()
+        .and_then(|input| input.parse::<DocumentMut>().ok())
+        .and_then(|mut doc| doc.remove(key))
+        .unwrap_or_default()
+}
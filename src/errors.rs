@@ -8,12 +8,33 @@ pub enum ProjectInitError {
     #[error("Failed to create project directory")]
     ProjectDirCreateFail,
 
+    #[error("Project directory is not empty")]
+    ProjectDirNotEmpty,
+
     #[error("Failed to clone repository")]
     GitCloneFail,
+
+    #[error("Failed to clone from every mirror:{0}")]
+    GitMirrorsFail(String),
     
+    #[error("Failed to fetch repository: {0}")]
+    GitFetchFail(String),
+
+    #[error("Failed to read CA bundle `{0}`: {1}")]
+    CaBundleReadFail(String, String),
+
     #[error("Git repository not found")]
     GitRepoNotFound,
 
+    #[error("Git authentication failed: {0}")]
+    GitAuthFail(String),
+
+    #[error("Git reference `{0}` not found")]
+    GitRefNotFound(String),
+
+    #[error("Failed to clone submodule: {0}")]
+    GitSubmoduleFail(String),
+
     #[error("Failed to write file in template: {0}")]
     WriteFileFail(String),
 
@@ -26,6 +47,60 @@ pub enum TemplateConfigError {
     #[error("Failed to read cpr.toml from template")]
     FileReadFail,
 
-    #[error("Failed to parse cpr.toml from template")]
-    TomlParseFail,
+    #[error("Templates extend each other: {0}")]
+    CyclicExtends(String),
+
+    #[error("Template `{0}` requires cpr {1}, this is cpr {2}")]
+    IncompatibleVersion(String, String, String),
+
+    #[error("Invalid choices for question `{0}`: {1}")]
+    InvalidChoices(String, String),
+
+    #[error("No shortcut key left for choice `{0}`, every letter of it is taken")]
+    NoShortcutKey(String),
+
+    #[error("Variables refer to each other: {0}")]
+    CyclicVariables(String),
+
+    #[error("Failed to evaluate variable `{0}`: {1}")]
+    VariableFail(String, String),
+
+    #[error("Unknown license `{0}`, expected one of {1}")]
+    UnknownLicense(String, String),
+
+    #[error("Template has {0} error(s)")]
+    LintFail(usize),
+
+    #[error("No fixtures found in `_tests/`")]
+    NoFixtures,
+
+    #[error("Fixture `{0}` has no answer for question `{1}`")]
+    MissingAnswer(String, String),
+
+    #[error("Fixture `{0}` answers `{1}` with `{2}`, which is not a license bundled with cpr")]
+    FixtureLicense(String, String, String),
+
+    #[error("{0} template test(s) failed")]
+    TestFail(usize),
+}
+
+#[derive(Debug, Error)]
+pub enum GeneratorError {
+    #[error("No generators found in .cpr/generators.toml")]
+    NoGenerators,
+
+    #[error("Generator `{0}` not found")]
+    NotFound(String),
+}
+
+#[derive(Debug, Error)]
+pub enum RegistryError {
+    #[error("Registry needs either a `path` or a `repo`")]
+    MissingSource,
+
+    #[error("Failed to read registry index `{0}`")]
+    IndexReadFail(String),
+
+    #[error("Failed to parse registry index `{0}`: {1}")]
+    JsonParseFail(String, String),
 }
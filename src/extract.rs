@@ -0,0 +1,260 @@
+use crate::errors::ProjectInitError;
+use crate::git;
+use crate::template;
+use miette::IntoDiagnostic;
+use std::path::{Path, PathBuf};
+
+/// Delimiters of template blocks, escaped in extracted files
+const DELIMITERS: &[&str] = &["{{", "{%", "{#", "}}", "%}", "#}"];
+
+/// Formatters joining the words of the name, preferred over the others and the raw name when
+/// they produce the same literal
+const JOINING_FORMATTERS: &[&str] = &["snake", "constant", "kebab", "pascal", "camel"];
+
+/// Entries of a project that never belong to its template
+const SKIPPED: &[&str] = &[".git", ".cpr"];
+
+/// Literals of a project and the template expressions replacing them, longest first
+pub struct Replacements {
+    pairs: Vec<(String, String)>,
+}
+
+impl Replacements {
+    /// Replace the project name in every casing a formatter produces, and the author and year
+    /// if given
+    pub fn new(name: &str, author: Option<&str>, year: Option<i32>) -> miette::Result<Self> {
+        // `my_project` must become `snake` rather than `lower` or the raw name, which would
+        // render other project names with spaces
+        let mut formatters = template::FORMATTERS.to_vec();
+        formatters.sort_by_key(|formatter| !JOINING_FORMATTERS.contains(formatter));
+        let mut pairs = Vec::new();
+        let engine = template::engine();
+        for formatter in formatters {
+            let literal = engine
+                .compile(format!("{{{{ name | {} }}}}", formatter))
+                .into_diagnostic()?
+                .render(&engine, upon::value! { name: name })
+                .to_string()
+                .into_diagnostic()?;
+            pairs.push((literal, format!("{{{{ project.name | {} }}}}", formatter)));
+        }
+        pairs.push((name.to_string(), "{{ project.name }}".to_string()));
+        if let Some(author) = author {
+            pairs.push((author.to_string(), "{{ author }}".to_string()));
+        }
+        if let Some(year) = year {
+            pairs.push((year.to_string(), "{{ year }}".to_string()));
+        }
+
+        // casings that look alike are replaced by the first expression producing them
+        let mut unique = Vec::<(String, String)>::new();
+        for (literal, expr) in pairs {
+            if !literal.is_empty() && !unique.iter().any(|(l, _)| *l == literal) {
+                unique.push((literal, expr));
+            }
+        }
+        // `my_project_test` must not match `my_project` first
+        unique.sort_by_key(|(literal, _)| std::cmp::Reverse(literal.len()));
+        Ok(Self { pairs: unique })
+    }
+
+    /// Template source rendering back to `text`
+    ///
+    /// Literals only match whole words, though identifiers may continue with an uppercase letter
+    /// or an underscore, e.g. `MyProjectConfig` or `my_project_version`.
+    pub fn apply(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut i = 0;
+        'text: while i < text.len() {
+            let rest = &text[i..];
+            // text that looks like a block renders as is
+            if let Some(delimiter) = DELIMITERS.iter().find(|d| rest.starts_with(**d)) {
+                push_block(&mut result, &format!("{{{{ \"{}\" }}}}", delimiter));
+                i += delimiter.len();
+                continue;
+            }
+            for (literal, expr) in &self.pairs {
+                if rest.starts_with(literal.as_str()) && is_word(text, i, i + literal.len()) {
+                    push_block(&mut result, expr);
+                    i += literal.len();
+                    continue 'text;
+                }
+            }
+            let c = rest.chars().next().unwrap();
+            // a brace right after a block would be read as part of its delimiter
+            if c == '}' && result.ends_with("}}") {
+                result.push_str("{{ \"}\" }}");
+            } else {
+                result.push(c);
+            }
+            i += c.len_utf8();
+        }
+        result
+    }
+}
+
+/// Append a `{{ ... }}` block, escaping a `{` right before it that would be read as part of its
+/// delimiter
+fn push_block(result: &mut String, block: &str) {
+    if result.ends_with('{') {
+        result.pop();
+        result.push_str("{{ \"{\" }}");
+    }
+    result.push_str(block);
+}
+
+fn is_word(text: &str, start: usize, end: usize) -> bool {
+    let before = text[..start].chars().next_back();
+    let after = text[end..].chars().next();
+    !before.is_some_and(|c| c.is_alphanumeric())
+        && !after.is_some_and(|c| c.is_lowercase() || c.is_numeric())
+}
+
+/// Copy the files of `project` into `template` with their paths and contents turned into
+/// template sources, returning the paths of the extracted files
+///
+/// Files ignored by git are left out, and files that aren't text are copied as they are.
+pub fn extract(
+    project: &Path,
+    template: &Path,
+    replacements: &Replacements,
+) -> miette::Result<Vec<PathBuf>> {
+    let ignored = git::ignore_rules(project);
+    let walker = walkdir::WalkDir::new(project)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0
+                || !(e.depth() == 1 && SKIPPED.iter().any(|s| e.file_name() == *s)
+                    || ignored(e.path()))
+        });
+
+    let mut extracted = Vec::new();
+    for entry in walker.filter_map(|e| e.ok()) {
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(project).unwrap();
+        if relative == Path::new("cpr.toml") {
+            eprintln!("! WARN: Skipping `cpr.toml`, the template gets a new one");
+            continue;
+        }
+        let path = PathBuf::from(replacements.apply(&relative.to_string_lossy()));
+        let target = template.join(&path);
+        std::fs::create_dir_all(target.parent().unwrap())
+            .map_err(|_| ProjectInitError::ProjectDirCreateFail)
+            .into_diagnostic()?;
+
+        let result = match std::fs::read_to_string(entry.path()) {
+            Ok(contents) => std::fs::write(&target, replacements.apply(&contents)),
+            Err(_) => std::fs::copy(entry.path(), &target).map(|_| ()),
+        };
+        result
+            .map_err(|_| ProjectInitError::WriteFileFail(path.display().to_string()))
+            .into_diagnostic()?;
+        log::debug!("extracted `{}` to `{}`", relative.display(), path.display());
+        extracted.push(path);
+    }
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replacements() -> Replacements {
+        Replacements::new("my project", Some("Jane Doe"), Some(2024)).unwrap()
+    }
+
+    /// Render extracted `source` back as the fixture of the project would
+    fn render(source: &str) -> String {
+        let engine = template::engine();
+        engine
+            .compile(source)
+            .unwrap()
+            .render(
+                &engine,
+                upon::value! {
+                    project: { name: "my project" },
+                    author: "Jane Doe",
+                    year: 2024,
+                },
+            )
+            .to_string()
+            .unwrap()
+    }
+
+    #[test]
+    fn replaces_the_name_in_every_casing() {
+        let replacements = replacements();
+        assert_eq!(
+            replacements.apply("my_project MY_PROJECT MyProject my-project myProject"),
+            "{{ project.name | snake }} {{ project.name | constant }} \
+             {{ project.name | pascal }} {{ project.name | kebab }} {{ project.name | camel }}"
+        );
+        assert_eq!(
+            replacements.apply("(c) 2024 Jane Doe"),
+            "(c) {{ year }} {{ author }}"
+        );
+    }
+
+    #[test]
+    fn prefers_joining_formatters_for_identifier_names() {
+        let replacements = Replacements::new("my_project", None, None).unwrap();
+        let source = replacements.apply("namespace my_project { class MyProject; } // MY_PROJECT");
+        assert_eq!(
+            source,
+            "namespace {{ project.name | snake }} { class {{ project.name | pascal }}; } \
+             // {{ project.name | constant }}"
+        );
+        assert_eq!(
+            render(&source),
+            "namespace my_project { class MyProject; } // MY_PROJECT"
+        );
+        let single_word = Replacements::new("project", None, None).unwrap();
+        assert_eq!(
+            single_word.apply("project PROJECT Project"),
+            "{{ project.name | snake }} {{ project.name | constant }} {{ project.name | pascal }}"
+        );
+    }
+
+    #[test]
+    fn replaces_whole_words_and_identifier_prefixes_only() {
+        let replacements = replacements();
+        assert_eq!(
+            replacements.apply("MyProjectConfig my_project_version MY_PROJECT_H"),
+            "{{ project.name | pascal }}Config {{ project.name | snake }}_version \
+             {{ project.name | constant }}_H"
+        );
+        for text in ["my_projects", "amy_project", "my_project2", "20245"] {
+            assert_eq!(replacements.apply(text), text);
+        }
+    }
+
+    #[test]
+    fn escapes_template_delimiters() {
+        let replacements = replacements();
+        assert_eq!(
+            replacements.apply("{{ x }} {% y %} {# z #}"),
+            r##"{{ "{{" }} x {{ "}}" }} {{ "{%" }} y {{ "%}" }} {{ "{#" }} z {{ "#}" }}"##
+        );
+        // braces next to a block would be read as part of its delimiter
+        assert_eq!(
+            replacements.apply("{MyProject} {#}"),
+            r##"{{ "{" }}{{ project.name | pascal }}{{ "}" }} {{ "{#" }}{{ "}" }}"##
+        );
+    }
+
+    #[test]
+    fn renders_back_to_the_original_text() {
+        let replacements = replacements();
+        for text in [
+            "namespace my_project { struct MyProjectConfig {}; }",
+            "#define MY_PROJECT_VERSION \"{{ not a block }}\" // {%%} {#}",
+            "# My Project\n\nCopyright (c) 2024 Jane Doe\n",
+            "auto config = {MyProject{}}; // {#} }}}",
+        ] {
+            assert_eq!(render(&replacements.apply(text)), text);
+        }
+    }
+}
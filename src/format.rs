@@ -29,6 +29,15 @@ pub fn snake(f: &mut fmt::Formatter, value: &Value) -> fmt::Result {
     Ok(())
 }
 
+pub fn constant(f: &mut fmt::Formatter, value: &Value) -> fmt::Result {
+    match value {
+        Value::None => Err(fmt::Error::from("unable to format None"))?,
+        Value::String(s) => write!(f, "{}", s.to_case(Case::ScreamingSnake))?,
+        _ => Err(fmt::Error::from("expected to format a string"))?,
+    }
+    Ok(())
+}
+
 pub fn kebab(f: &mut fmt::Formatter, value: &Value) -> fmt::Result {
     match value {
         Value::None => Err(fmt::Error::from("unable to format None"))?,
@@ -0,0 +1,557 @@
+use crate::config::{BaseURL, Config};
+use crate::errors::ProjectInitError;
+use crate::repo::RepoPath;
+use crate::tls::{self, CaBundle};
+use git2::{
+    build::{CheckoutBuilder, RepoBuilder},
+    CertificateCheckStatus, Cred, CredentialType, FetchOptions, ProxyOptions, RemoteCallbacks,
+    Repository, SubmoduleUpdateOptions,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use miette::IntoDiagnostic;
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+};
+
+/// Credentials offered to the remote, each one is tried at most once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CredentialKind {
+    SshKey,
+    SshAgent,
+    Token,
+    CredentialHelper,
+    Username,
+    Default,
+}
+
+impl CredentialKind {
+    fn describe(&self) -> &'static str {
+        match self {
+            CredentialKind::SshKey => "ssh key",
+            CredentialKind::SshAgent => "ssh-agent",
+            CredentialKind::Token => "token",
+            CredentialKind::CredentialHelper => "git credential helper",
+            CredentialKind::Username => "username",
+            CredentialKind::Default => "default credentials",
+        }
+    }
+}
+
+struct Credentials<'a> {
+    service: &'a BaseURL,
+    tried: Vec<CredentialKind>,
+    exhausted: bool,
+}
+
+impl<'a> Credentials<'a> {
+    fn new(service: &'a BaseURL) -> Self {
+        Self {
+            service,
+            tried: Vec::new(),
+            exhausted: false,
+        }
+    }
+
+    /// Next credential to try for the allowed types, libgit2 calls back again whenever the
+    /// previous credential is rejected
+    fn next(
+        &mut self,
+        url: &str,
+        username_from_url: Option<&str>,
+        allowed: CredentialType,
+    ) -> Result<Cred, git2::Error> {
+        let username = username_from_url
+            .or(self.service.user.as_deref())
+            .unwrap_or("git");
+
+        let mut candidates = Vec::new();
+        if allowed.contains(CredentialType::SSH_KEY) {
+            if self.service.ssh_key.is_some() {
+                candidates.push(CredentialKind::SshKey);
+            }
+            candidates.push(CredentialKind::SshAgent);
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if self.service.token_env.is_some() {
+                candidates.push(CredentialKind::Token);
+            }
+            candidates.push(CredentialKind::CredentialHelper);
+        }
+        if allowed.contains(CredentialType::USERNAME) {
+            candidates.push(CredentialKind::Username);
+        }
+        if allowed.contains(CredentialType::DEFAULT) {
+            candidates.push(CredentialKind::Default);
+        }
+
+        for kind in candidates {
+            if self.tried.contains(&kind) {
+                continue;
+            }
+            self.tried.push(kind);
+            log::debug!("trying {} for {}", kind.describe(), url);
+
+            let cred = match kind {
+                CredentialKind::SshKey => {
+                    let key = self.service.ssh_key.as_ref().unwrap();
+                    Cred::ssh_key(username, None, key, None)
+                }
+                CredentialKind::SshAgent => Cred::ssh_key_from_agent(username),
+                CredentialKind::Token => {
+                    let var = self.service.token_env.as_ref().unwrap();
+                    match std::env::var(var) {
+                        Ok(token) => Cred::userpass_plaintext(username, &token),
+                        Err(_) => {
+                            log::warn!("environment variable `{}` is not set", var);
+                            continue;
+                        }
+                    }
+                }
+                CredentialKind::CredentialHelper => git2::Config::open_default()
+                    .and_then(|config| Cred::credential_helper(&config, url, username_from_url)),
+                CredentialKind::Username => Cred::username(username),
+                CredentialKind::Default => Cred::default(),
+            };
+            match cred {
+                Ok(cred) => return Ok(cred),
+                Err(e) => log::debug!("{} unavailable: {}", kind.describe(), e.message()),
+            }
+        }
+
+        self.exhausted = true;
+        Err(git2::Error::from_str(&format!(
+            "no accepted credentials for {}",
+            url
+        )))
+    }
+
+    fn describe_tried(&self) -> String {
+        if self.tried.is_empty() {
+            return "nothing".to_string();
+        }
+        self.tried
+            .iter()
+            .map(|kind| kind.describe())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+struct GitCloneState {
+    total: usize,
+    current: usize,
+    path: Option<PathBuf>,
+    started_resolution: bool,
+}
+
+/// Service a repository is fetched from, along with what its callbacks need
+struct Remote<'a, 's> {
+    service: &'s BaseURL,
+    url: &'a str,
+    credentials: &'a RefCell<Credentials<'s>>,
+    ca_bundle: Option<&'a CaBundle>,
+}
+
+/// Callbacks shared by every fetch from a service: authentication and certificate checks
+fn remote_callbacks<'cb>(remote: &Remote<'cb, '_>) -> RemoteCallbacks<'cb> {
+    let Remote {
+        service,
+        url,
+        credentials,
+        ca_bundle,
+    } = *remote;
+    let mut cb = RemoteCallbacks::new();
+    cb.credentials(|url, username_from_url, allowed| {
+        credentials
+            .borrow_mut()
+            .next(url, username_from_url, allowed)
+    });
+    if service.insecure {
+        cb.certificate_check(|_, host| {
+            log::warn!("skipping certificate check for {}", host);
+            Ok(CertificateCheckStatus::CertificateOk)
+        });
+    } else if let Some(ca_bundle) = ca_bundle {
+        // certificates the bundle doesn't vouch for are checked against the system roots
+        cb.certificate_check(move |cert, host| {
+            let Some(x509) = cert.as_x509() else {
+                return Ok(CertificateCheckStatus::CertificatePassthrough);
+            };
+            // libgit2 only passes the leaf, the intermediates are read from the server itself
+            let verified = ca_bundle.verifies(x509.data(), &[], host) || {
+                let port = https_port(url, host);
+                match tls::presented_intermediates(host, port, service.proxy.as_deref()) {
+                    Ok(intermediates) => ca_bundle.verifies(x509.data(), &intermediates, host),
+                    Err(e) => {
+                        log::debug!("failed to read the certificates of {}: {}", host, e);
+                        false
+                    }
+                }
+            };
+            if verified {
+                log::debug!("certificate of {} verified with the CA bundle", host);
+                Ok(CertificateCheckStatus::CertificateOk)
+            } else {
+                Ok(CertificateCheckStatus::CertificatePassthrough)
+            }
+        });
+    }
+    cb
+}
+
+/// Port of `url` if it points at `host`, the HTTPS one otherwise, e.g. for submodules hosted
+/// elsewhere
+fn https_port(url: &str, host: &str) -> u16 {
+    url::Url::parse(url)
+        .ok()
+        .filter(|url| url.host_str() == Some(host))
+        .and_then(|url| url.port())
+        .unwrap_or(443)
+}
+
+fn fetch_options<'a>(service: &BaseURL, cb: RemoteCallbacks<'a>) -> FetchOptions<'a> {
+    let mut fo = FetchOptions::new();
+    fo.remote_callbacks(cb);
+    if let Some(proxy) = &service.proxy {
+        let mut po = ProxyOptions::new();
+        po.url(proxy);
+        fo.proxy_options(po);
+    }
+    if let Some(depth) = service.depth {
+        fo.depth(depth);
+    }
+    fo
+}
+
+fn clone_error(e: git2::Error, credentials: &Credentials) -> ProjectInitError {
+    log::debug!("clone failed: {}", e.message());
+    if e.code() == git2::ErrorCode::NotFound {
+        ProjectInitError::GitRepoNotFound
+    } else if e.code() == git2::ErrorCode::Auth || credentials.exhausted {
+        ProjectInitError::GitAuthFail(format!(
+            "{} (tried {})",
+            e.message(),
+            credentials.describe_tried()
+        ))
+    } else {
+        ProjectInitError::GitFetchFail(e.message().to_string())
+    }
+}
+
+/// Whether paths under `directory` are ignored by the repository it belongs to, if any
+pub fn ignore_rules(directory: &Path) -> impl Fn(&Path) -> bool {
+    let repo = Repository::discover(directory).ok();
+    let workdir = repo
+        .as_ref()
+        .and_then(|repo| repo.workdir())
+        .and_then(|workdir| workdir.canonicalize().ok());
+    move |path| {
+        let (Some(repo), Some(workdir)) = (&repo, &workdir) else {
+            return false;
+        };
+        let Some(path) = path.canonicalize().ok() else {
+            return false;
+        };
+        let Ok(relative) = path.strip_prefix(workdir) else {
+            return false;
+        };
+        // directories only match patterns such as `build/` with a trailing slash
+        let relative = if path.is_dir() {
+            relative.join("")
+        } else {
+            relative.to_path_buf()
+        };
+        repo.is_path_ignored(relative).unwrap_or(false)
+    }
+}
+
+/// Start an empty repository, e.g. for a new template
+pub fn init_repository(directory: &Path) -> miette::Result<()> {
+    log::debug!("initializing repository in {:?}", directory);
+    Repository::init(directory).into_diagnostic()?;
+    Ok(())
+}
+
+pub fn clone_repository(
+    directory: &Path,
+    repo_path: &RepoPath,
+    config: &Config,
+) -> miette::Result<()> {
+    // if prefix is not provided, use the default prefix
+    log::debug!("repo_path: {}", repo_path);
+    let prefix = repo_path
+        .prefix
+        .as_deref()
+        .unwrap_or(&config.default_service);
+    let urls = config.clone_urls(prefix, repo_path)?;
+    let service = config.service(prefix)?;
+
+    let ca_bundle = match &service.ca_bundle {
+        Some(path) => {
+            log::debug!("using CA bundle: {:?}", path);
+            let bundle = CaBundle::read(path)
+                .map_err(|e| ProjectInitError::CaBundleReadFail(path.display().to_string(), e))
+                .into_diagnostic()?;
+            Some(bundle)
+        }
+        None => None,
+    };
+
+    let mut failures = Vec::new();
+    for (attempt, url) in urls.iter().enumerate() {
+        let credentials = RefCell::new(Credentials::new(service));
+        let remote = Remote {
+            service,
+            url,
+            credentials: &credentials,
+            ca_bundle: ca_bundle.as_ref(),
+        };
+        let repo = match clone_from(url, directory, repo_path, &remote) {
+            Ok(repo) => repo,
+            Err(e) => {
+                if urls.len() > 1 {
+                    eprintln!(
+                        "! WARN: Failed to clone from `{}` ({}/{}): {}",
+                        url,
+                        attempt + 1,
+                        urls.len(),
+                        e
+                    );
+                }
+                failures.push((url.as_str(), e));
+                continue;
+            }
+        };
+        if attempt > 0 {
+            println!(
+                "Cloned {} from `{}` after {} failed attempt(s)",
+                repo_path, url, attempt
+            );
+        } else if urls.len() > 1 {
+            println!("Cloned {} from `{}`", repo_path, url);
+        }
+
+        let reference = repo_path
+            .reference
+            .as_deref()
+            .or(service.default_ref.as_deref());
+        if let Some(reference) = reference {
+            checkout_reference(&repo, reference, &remote)?;
+        }
+
+        if service.submodules {
+            update_submodules(&repo, &remote)?;
+        }
+
+        drop(repo);
+
+        // let users decide their own vcs configuration
+        return remove_git_dirs(directory);
+    }
+    let error = match failures.len() {
+        0 => ProjectInitError::GitCloneFail,
+        1 => failures.remove(0).1,
+        _ => ProjectInitError::GitMirrorsFail(list_failures(&failures)),
+    };
+    Err(error).into_diagnostic()
+}
+
+/// List every mirror that failed to clone, one per line
+fn list_failures(failures: &[(&str, ProjectInitError)]) -> String {
+    failures
+        .iter()
+        .map(|(url, e)| format!("\n  - `{}`: {}", url, e))
+        .collect()
+}
+
+/// Clone a repository from a single URL, showing progress
+fn clone_from(
+    url: &str,
+    directory: &Path,
+    repo_path: &RepoPath,
+    remote: &Remote,
+) -> Result<Repository, ProjectInitError> {
+    let clone_state = RefCell::new(GitCloneState {
+        total: 0,
+        current: 0,
+        path: None,
+        started_resolution: false,
+    });
+    let bar = ProgressBar::new(100);
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{spinner} [{elapsed_precise}] [{bar:30}] {pos:>7}/{len:7} {msg}",
+        )
+        .unwrap()
+        .progress_chars("#>-"),
+    );
+
+    let mut cb = remote_callbacks(remote);
+    cb.transfer_progress(|stats| {
+        let mut state = clone_state.borrow_mut();
+        state.total = stats.total_objects();
+        state.current = stats.received_objects();
+        if state.current == state.total {
+            // resolving deltas
+            bar.set_message(format!(
+                "Resolving deltas {}/{}",
+                stats.indexed_deltas(),
+                stats.total_deltas()
+            ));
+            if state.started_resolution {
+                bar.reset();
+            }
+            bar.set_length(stats.total_deltas() as u64);
+            bar.set_position(stats.indexed_deltas() as u64);
+            state.started_resolution = true;
+        } else {
+            bar.set_length(state.total as u64);
+            bar.set_position(state.current as u64);
+            bar.set_message(format!(
+                "Cloning {} ({}/{} objects)",
+                repo_path, state.current, state.total
+            ));
+            bar.tick();
+        }
+        true
+    });
+
+    let mut co = CheckoutBuilder::new();
+    co.progress(|path, cur, total| {
+        let mut state = clone_state.borrow_mut();
+        state.path = path.map(|p| p.to_path_buf());
+        state.current = cur;
+        state.total = total;
+        if cur < total {
+            bar.set_length(total as u64);
+            bar.set_position(cur as u64);
+            bar.set_message(format!(
+                "Cloning {} ({}/{} objects)",
+                repo_path, state.current, state.total
+            ));
+            bar.tick();
+        }
+    });
+
+    log::debug!("cloning repository: {}", url);
+
+    let repo = RepoBuilder::new()
+        .fetch_options(fetch_options(remote.service, cb))
+        .with_checkout(co)
+        .clone(url, directory);
+    repo.map_err(|e| {
+        bar.finish_and_clear();
+        clone_error(e, &remote.credentials.borrow())
+    })
+}
+
+/// Check out a branch, tag or commit of a freshly cloned repository
+fn checkout_reference(repo: &Repository, reference: &str, remote: &Remote) -> miette::Result<()> {
+    log::debug!("checking out reference: {}", reference);
+    // remote branches only exist as `origin/<branch>` after cloning
+    let object = match repo
+        .revparse_single(&format!("origin/{}", reference))
+        .or_else(|_| repo.revparse_single(reference))
+    {
+        Ok(object) => object,
+        Err(_) => {
+            // shallow clones only contain the default branch, fetch the reference explicitly
+            log::debug!("fetching reference: {}", reference);
+            repo.find_remote("origin")
+                .and_then(|mut origin| {
+                    origin.fetch(
+                        &[reference],
+                        Some(&mut fetch_options(remote.service, remote_callbacks(remote))),
+                        None,
+                    )
+                })
+                .and_then(|_| repo.revparse_single("FETCH_HEAD"))
+                .map_err(|_| ProjectInitError::GitRefNotFound(reference.to_string()))
+                .into_diagnostic()?
+        }
+    };
+    repo.checkout_tree(&object, Some(CheckoutBuilder::new().force()))
+        .map_err(|_| ProjectInitError::GitCloneFail)
+        .into_diagnostic()?;
+    repo.set_head_detached(object.id())
+        .map_err(|_| ProjectInitError::GitCloneFail)
+        .into_diagnostic()
+}
+
+/// Clone every submodule of the repository, recursively
+fn update_submodules(repo: &Repository, remote: &Remote) -> miette::Result<()> {
+    let submodules = repo
+        .submodules()
+        .map_err(|_| ProjectInitError::GitCloneFail)
+        .into_diagnostic()?;
+    for mut submodule in submodules {
+        let name = submodule.name().unwrap_or_default().to_string();
+        log::debug!("updating submodule: {}", name);
+
+        let mut opts = SubmoduleUpdateOptions::new();
+        opts.fetch(fetch_options(remote.service, remote_callbacks(remote)));
+        submodule
+            .update(true, Some(&mut opts))
+            .map_err(|e| {
+                log::debug!("submodule update failed: {}", e.message());
+                ProjectInitError::GitSubmoduleFail(name.clone())
+            })
+            .into_diagnostic()?;
+
+        let submodule_repo = submodule
+            .open()
+            .map_err(|_| ProjectInitError::GitSubmoduleFail(name))
+            .into_diagnostic()?;
+        update_submodules(&submodule_repo, remote)?;
+    }
+    Ok(())
+}
+
+/// Remove the `.git` directory of the repository and the `.git` files of its submodules
+fn remove_git_dirs(directory: &Path) -> miette::Result<()> {
+    let git_dirs = walkdir::WalkDir::new(directory)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || e.file_name() != ".git" || !e.file_type().is_dir())
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name() == ".git")
+        .map(|e| e.into_path())
+        .chain(std::iter::once(directory.join(".git")))
+        .collect::<Vec<_>>();
+    for path in git_dirs {
+        let removed = if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        removed
+            .map_err(|_| ProjectInitError::GitCloneFail)
+            .into_diagnostic()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_every_failed_mirror() {
+        let failures = [
+            (
+                "https://a.example/repo.git",
+                ProjectInitError::GitRepoNotFound,
+            ),
+            (
+                "https://b.example/repo.git",
+                ProjectInitError::GitAuthFail("no credentials".into()),
+            ),
+        ];
+        assert_eq!(
+            ProjectInitError::GitMirrorsFail(list_failures(&failures)).to_string(),
+            "Failed to clone from every mirror:\n  \
+             - `https://a.example/repo.git`: Git repository not found\n  \
+             - `https://b.example/repo.git`: Git authentication failed: no credentials"
+        );
+    }
+}
@@ -0,0 +1,32 @@
+/// SPDX identifiers and texts of the licenses bundled with cpr
+///
+/// Texts may reference `{{ year }}` and `{{ author }}` and are rendered with the
+/// same engine as the template files.
+pub const LICENSES: &[(&str, &str)] = &[
+    ("MIT", include_str!("licenses/MIT.txt")),
+    ("Apache-2.0", include_str!("licenses/Apache-2.0.txt")),
+    ("BSD-2-Clause", include_str!("licenses/BSD-2-Clause.txt")),
+    ("BSD-3-Clause", include_str!("licenses/BSD-3-Clause.txt")),
+    ("GPL-3.0-only", include_str!("licenses/GPL-3.0-only.txt")),
+    ("MPL-2.0", include_str!("licenses/MPL-2.0.txt")),
+    ("Unlicense", include_str!("licenses/Unlicense.txt")),
+];
+
+pub fn ids() -> impl Iterator<Item = &'static str> {
+    LICENSES.iter().map(|(id, _)| *id)
+}
+
+pub fn text(id: &str) -> Option<&'static str> {
+    LICENSES
+        .iter()
+        .find(|(license, _)| *license == id)
+        .map(|(_, text)| *text)
+}
+
+/// `spdx_header` filter, e.g. `{{ cpr.license | spdx_header }}` -> `// SPDX-License-Identifier: MIT`
+pub fn spdx_header(id: &str) -> Result<String, String> {
+    if text(id).is_none() {
+        return Err(format!("unknown SPDX license identifier `{}`", id));
+    }
+    Ok(format!("// SPDX-License-Identifier: {}", id))
+}
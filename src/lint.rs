@@ -0,0 +1,1251 @@
+use crate::license;
+use crate::manifest;
+use crate::manifest::{
+    Choice, Choices, GeneratorFile, Layer, QuestionKind, TemplateInfo, TemplateManifest,
+    TemplateQuestion,
+};
+use crate::snapshot;
+use crate::template;
+use crate::variables;
+use miette::{Diagnostic, IntoDiagnostic, LabeledSpan, NamedSource, Severity, SourceCode};
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::Path;
+use toml_edit::{ImDocument, InlineTable, Item, TableLike, Value};
+
+/// Variables available to the files of a template
+const TEMPLATE_ROOTS: &[&str] = &["project", "year", "author", "cpr", "vars"];
+
+/// Variables available to the files of a generator
+const GENERATOR_ROOTS: &[&str] = &["project", "year", "author", "cpr", "vars", "gen"];
+
+/// Words of the template syntax that are never variables
+const KEYWORDS: &[&str] = &[
+    "if", "not", "else", "endif", "for", "in", "endfor", "with", "as", "endwith", "include",
+    "true", "false",
+];
+
+/// A problem found in a template, pointing into the file it was found in
+#[derive(Debug)]
+pub struct LintProblem {
+    message: String,
+    label: String,
+    source: NamedSource<String>,
+    span: Option<Range<usize>>,
+    severity: Severity,
+}
+
+impl LintProblem {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl std::fmt::Display for LintProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for LintProblem {}
+
+impl Diagnostic for LintProblem {
+    fn severity(&self) -> Option<Severity> {
+        Some(self.severity)
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        Some(&self.source)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let span = self.span.clone()?;
+        Some(Box::new(std::iter::once(LabeledSpan::new_with_span(
+            Some(self.label.clone()),
+            span,
+        ))))
+    }
+}
+
+/// Check the `cpr.toml` and files of the template at `root`
+pub fn lint(root: &Path) -> miette::Result<Vec<LintProblem>> {
+    let mut linter = Linter {
+        root,
+        problems: Vec::new(),
+        partials: partial_names(root),
+    };
+
+    let manifest_path = root.join("cpr.toml");
+    let manifest = if manifest_path.exists() {
+        let file = SourceFile::read(root, &manifest_path)?;
+        match linter.check_manifest(&file) {
+            Some(manifest) => manifest,
+            // names of questions are unknown past a syntax error
+            None => return Ok(linter.problems),
+        }
+    } else {
+        Manifest::default()
+    };
+
+    for path in snapshot::fixture_paths(root) {
+        let file = SourceFile::read(root, &path)?;
+        linter.check_fixture(&file, &manifest);
+    }
+
+    let walker = walkdir::WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() != 1
+                || ![".git", "cpr.toml", snapshot::TESTS_DIR]
+                    .iter()
+                    .any(|n| e.file_name() == *n)
+        });
+    for entry in walker.filter_map(|e| e.ok()) {
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).unwrap();
+        let mut components = relative.components();
+        let scope = match components.next().and_then(|c| c.as_os_str().to_str()) {
+            Some("_generators") => {
+                let name = components.next().and_then(|c| c.as_os_str().to_str());
+                let Some(generator) = manifest
+                    .generators
+                    .iter()
+                    .find(|g| Some(g.name.as_str()) == name)
+                else {
+                    linter.warn(
+                        &SourceFile::unread(root, entry.path()),
+                        None,
+                        "file belongs to no generator declared in cpr.toml",
+                        "",
+                    );
+                    continue;
+                };
+                Scope {
+                    roots: GENERATOR_ROOTS,
+                    answers: generator.answers(&manifest.keys),
+                    variables: manifest.variables.clone(),
+                    bases: manifest.bases,
+                }
+            }
+            // files and partials share the context of the template
+            _ => Scope {
+                roots: TEMPLATE_ROOTS,
+                answers: manifest.keys.clone(),
+                variables: manifest.variables.clone(),
+                bases: manifest.bases,
+            },
+        };
+        // paths are rendered like files
+        let path = SourceFile::unread(root, entry.path());
+        if path.name.contains("{{") || path.name.contains("{%") {
+            let path = SourceFile {
+                text: path.name.clone(),
+                ..path
+            };
+            linter.check_template(&path, &path.text, 0, &scope);
+        }
+        // files that aren't text are copied as they are
+        let Ok(file) = SourceFile::read(root, entry.path()) else {
+            continue;
+        };
+        linter.check_template(&file, &file.text, 0, &scope);
+    }
+
+    Ok(linter.problems)
+}
+
+/// Questions and generators declared by a `cpr.toml`
+#[derive(Default)]
+struct Manifest {
+    keys: Vec<String>,
+    /// Keys of the `license` questions
+    licenses: Vec<String>,
+    /// Names of the `[variables]`
+    variables: Vec<String>,
+    /// The template extends or layers other templates, which may declare more questions
+    bases: bool,
+    generators: Vec<Generator>,
+}
+
+struct Generator {
+    name: String,
+    keys: Vec<String>,
+}
+
+impl Generator {
+    /// Answers available to the generator: those of the template and its own
+    fn answers(&self, template_keys: &[String]) -> Vec<String> {
+        template_keys.iter().chain(&self.keys).cloned().collect()
+    }
+}
+
+/// What a template can refer to
+struct Scope {
+    roots: &'static [&'static str],
+    answers: Vec<String>,
+    variables: Vec<String>,
+    bases: bool,
+}
+
+struct SourceFile {
+    name: String,
+    text: String,
+}
+
+impl SourceFile {
+    fn read(root: &Path, path: &Path) -> miette::Result<Self> {
+        let mut file = Self::unread(root, path);
+        file.text = std::fs::read_to_string(path).into_diagnostic()?;
+        Ok(file)
+    }
+
+    /// A file reported on without its contents
+    fn unread(root: &Path, path: &Path) -> Self {
+        let name = path.strip_prefix(root).unwrap_or(path);
+        Self {
+            name: name.to_string_lossy().replace('\\', "/"),
+            text: String::new(),
+        }
+    }
+
+    /// Offset of the contents of a string value, after its opening quotes
+    fn string_start(&self, span: &Range<usize>) -> usize {
+        let raw = &self.text[span.clone()];
+        span.start + raw.len() - raw.trim_start_matches(['"', '\'']).len()
+    }
+}
+
+struct Linter<'a> {
+    root: &'a Path,
+    problems: Vec<LintProblem>,
+    /// Names the template's partials are included by
+    partials: Vec<String>,
+}
+
+impl Linter<'_> {
+    fn report(
+        &mut self,
+        severity: Severity,
+        file: &SourceFile,
+        span: Option<Range<usize>>,
+        message: impl Into<String>,
+        label: impl Into<String>,
+    ) {
+        self.problems.push(LintProblem {
+            message: format!("{}: {}", file.name, message.into()),
+            label: label.into(),
+            source: NamedSource::new(&file.name, file.text.clone()),
+            span,
+            severity,
+        });
+    }
+
+    fn error(
+        &mut self,
+        file: &SourceFile,
+        span: Option<Range<usize>>,
+        message: impl Into<String>,
+        label: impl Into<String>,
+    ) {
+        self.report(Severity::Error, file, span, message, label);
+    }
+
+    fn warn(
+        &mut self,
+        file: &SourceFile,
+        span: Option<Range<usize>>,
+        message: impl Into<String>,
+        label: impl Into<String>,
+    ) {
+        self.report(Severity::Warning, file, span, message, label);
+    }
+
+    fn check_manifest(&mut self, file: &SourceFile) -> Option<Manifest> {
+        let doc = match ImDocument::parse(file.text.as_str()) {
+            Ok(doc) => doc,
+            Err(e) => {
+                self.error(file, e.span(), "invalid TOML", e.message().trim());
+                return None;
+            }
+        };
+        let root = doc.as_table();
+        // the structure is checked by deserializing it as cpr does, the document only locates
+        // the problems found past that. Entries of questions, layers and generators are
+        // deserialized one by one so that each broken entry is reported.
+        let mut rest = root.clone();
+        let questions = rest.remove("questions");
+        let layers = rest.remove("layers");
+        let generators = rest.remove("generators");
+        let parsed = self.deserialize::<TemplateManifest>(file, &rest, None);
+
+        let question_tables = questions.as_ref().and_then(tables).unwrap_or_default();
+        let mut parsed_questions = Vec::new();
+        // broken questions still answer to their key, so references to them aren't reported too
+        let mut broken_keys = Vec::new();
+        for (table, span) in question_tables {
+            match self.deserialize::<TemplateQuestion>(file, table, span.clone()) {
+                Some(question) => parsed_questions.push((question, (table, span))),
+                None => {
+                    broken_keys.extend(table.get("key").and_then(Item::as_str).map(String::from))
+                }
+            }
+        }
+        let layer_tables = layers.as_ref().and_then(tables).unwrap_or_default();
+        let parsed_layers = layer_tables
+            .iter()
+            .filter_map(|(table, span)| self.deserialize::<Layer>(file, *table, span.clone()))
+            .collect::<Vec<_>>();
+        let generator_tables = generators.as_ref().and_then(Item::as_table_like);
+        let mut parsed_generators = BTreeMap::new();
+        for (name, item) in generator_tables.iter().flat_map(|table| table.iter()) {
+            let Some(table) = item.as_table_like() else {
+                self.error(
+                    file,
+                    item.span(),
+                    "invalid cpr.toml",
+                    format!("generator `{}` is not a table", name),
+                );
+                continue;
+            };
+            if let Some(generator) = self.deserialize(file, table, item.span()) {
+                parsed_generators.insert(name.to_string(), generator);
+            }
+        }
+        let mut parsed = parsed?;
+        parsed.questions = parsed_questions.iter().map(|(q, _)| q.clone()).collect();
+        parsed.layers = parsed_layers;
+        parsed.generators = parsed_generators;
+
+        let mut manifest = Manifest {
+            bases: parsed.extends.is_some() || !parsed.layers.is_empty(),
+            variables: parsed.variables.keys().cloned().collect(),
+            licenses: parsed
+                .questions
+                .iter()
+                .filter(|q| matches!(q.kind, QuestionKind::License))
+                .map(|q| q.key.clone())
+                .collect(),
+            ..Manifest::default()
+        };
+
+        for key in parsed.extra.keys() {
+            self.error(
+                file,
+                key_span(root, key),
+                format!("unknown key `{}`", key),
+                "not a key of cpr.toml",
+            );
+        }
+        if let Some(template) = root.get("template").and_then(Item::as_table_like) {
+            self.check_fields::<TemplateInfo>(file, template, "in [template]");
+            self.check_versions(file, &parsed.template, template);
+        }
+        for (layer, _) in root.get("layers").and_then(tables).unwrap_or_default() {
+            self.check_fields::<Layer>(file, layer, "for a layer");
+        }
+        let questions = parsed_questions
+            .iter()
+            .map(|(question, table)| (question, table.clone()))
+            .collect::<Vec<_>>();
+        manifest.keys = self.check_questions(file, &questions, &broken_keys, manifest.bases);
+        manifest.keys.extend(broken_keys);
+        if let Some(generators) = root.get("generators").and_then(Item::as_table_like) {
+            manifest.generators =
+                self.check_generators(file, &parsed.generators, generators, &manifest);
+        }
+        // variables may refer to answers of questions declared after them
+        if let Some(variables) = root.get("variables").and_then(Item::as_table_like) {
+            self.check_variables(file, &parsed.variables, variables, &manifest);
+        }
+
+        Some(manifest)
+    }
+
+    /// Deserialize `table` as `T`, reporting why it can't be at the problem or else at `span`
+    fn deserialize<T: DeserializeOwned>(
+        &mut self,
+        file: &SourceFile,
+        table: &dyn TableLike,
+        span: Option<Range<usize>>,
+    ) -> Option<T> {
+        match T::deserialize(Value::InlineTable(inline_table(table)).into_deserializer()) {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                self.error(
+                    file,
+                    e.span().or(span),
+                    "invalid cpr.toml",
+                    e.message().trim(),
+                );
+                None
+            }
+        }
+    }
+
+    /// Report the fields of `table` that deserializing it as `T` ignores
+    fn check_fields<T: DeserializeOwned>(
+        &mut self,
+        file: &SourceFile,
+        table: &dyn TableLike,
+        what: &str,
+    ) {
+        for field in ignored_fields::<T>(table) {
+            self.error(
+                file,
+                key_span(table, field),
+                format!("unknown field `{}` {}", field, what),
+                "cpr ignores this field",
+            );
+        }
+    }
+
+    /// Check the answers of a fixture in `_tests/`
+    fn check_fixture(&mut self, file: &SourceFile, manifest: &Manifest) {
+        let doc = match ImDocument::parse(file.text.as_str()) {
+            Ok(doc) => doc,
+            Err(e) => {
+                self.error(file, e.span(), "invalid TOML", e.message().trim());
+                return;
+            }
+        };
+        let Some(answers) = doc.as_table().get("answers").and_then(Item::as_table_like) else {
+            return;
+        };
+        for key in &manifest.licenses {
+            let Some(answer) = answers.get(key) else {
+                continue;
+            };
+            if answer
+                .as_str()
+                .is_some_and(|id| license::text(id).is_some())
+            {
+                continue;
+            }
+            self.error(
+                file,
+                answer.span(),
+                format!("unknown license for `{}`", key),
+                format!(
+                    "expected one of {}",
+                    license::ids().collect::<Vec<_>>().join(", ")
+                ),
+            );
+        }
+    }
+
+    fn check_variables(
+        &mut self,
+        file: &SourceFile,
+        expressions: &BTreeMap<String, String>,
+        table: &dyn TableLike,
+        manifest: &Manifest,
+    ) {
+        let scope = Scope {
+            roots: TEMPLATE_ROOTS,
+            answers: manifest.keys.clone(),
+            variables: manifest.variables.clone(),
+            bases: manifest.bases,
+        };
+        for (name, expr) in expressions {
+            let Some(item) = table.get(name) else {
+                continue;
+            };
+            let (source, _) = template::expression_source(expr);
+            let prefix = source.find(expr.as_str()).unwrap_or_default();
+            self.check_expression(file, item, &source, prefix, "invalid variable", &scope);
+        }
+
+        if let Err(cycle) = variables::order(expressions) {
+            self.error(
+                file,
+                table.get(&cycle[0]).and_then(Item::span),
+                format!("variables refer to each other: {}", cycle.join(" -> ")),
+                "refers back to itself",
+            );
+        }
+    }
+
+    fn check_versions(&mut self, file: &SourceFile, info: &TemplateInfo, table: &dyn TableLike) {
+        if let (Some(min), Some(max)) = (&info.min_cpr_version, &info.max_cpr_version) {
+            if max < min {
+                self.error(
+                    file,
+                    table.get("max_cpr_version").and_then(Item::span),
+                    "`max_cpr_version` is older than `min_cpr_version`",
+                    "no version of cpr can render this template",
+                );
+            }
+        }
+    }
+
+    /// Check questions along with the tables they were deserialized from, returning their keys
+    fn check_questions(
+        &mut self,
+        file: &SourceFile,
+        questions: &[(&TemplateQuestion, SpannedTable)],
+        earlier: &[String],
+        bases: bool,
+    ) -> Vec<String> {
+        let mut keys = Vec::<String>::new();
+        for (question, (table, _)) in questions {
+            let table = *table;
+            if keys.contains(&question.key) || earlier.contains(&question.key) {
+                self.error(
+                    file,
+                    table.get("key").and_then(Item::span),
+                    format!("duplicate question key `{}`", question.key),
+                    "already used by an earlier question",
+                );
+            } else {
+                keys.push(question.key.clone());
+            }
+            let ty = table.get("type").and_then(Item::as_str).unwrap_or_default();
+            self.check_fields::<TemplateQuestion>(file, table, &format!("for a `{}` question", ty));
+
+            // questions can only depend on the answers given before them, list entries too
+            let answers = earlier
+                .iter()
+                .chain(&keys)
+                .filter(|k| **k != question.key)
+                .cloned()
+                .collect::<Vec<_>>();
+            let scope = || Scope {
+                roots: &["cpr"],
+                answers: answers.clone(),
+                variables: Vec::new(),
+                bases,
+            };
+            if let Some(when) = table.get("when") {
+                self.check_when(file, when, scope());
+            }
+            match (&question.kind, question.kind.choices()) {
+                (QuestionKind::Input { .. } | QuestionKind::Password { .. }, _) => {
+                    if let Some(validate) = table.get("validate") {
+                        self.check_validate(file, validate);
+                    }
+                }
+                (QuestionKind::List { questions }, _) => {
+                    if let Some(nested) = table.get("questions").and_then(tables) {
+                        let nested = questions.iter().zip(nested).collect::<Vec<_>>();
+                        self.check_questions(file, &nested, &answers, bases);
+                    }
+                }
+                (kind, Some(choices)) => {
+                    if let Some(item) = table.get("choices") {
+                        let expand = matches!(kind, QuestionKind::Expand { .. });
+                        self.check_choices(file, choices, item, expand, scope());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        keys
+    }
+
+    fn check_when(&mut self, file: &SourceFile, item: &Item, scope: Scope) {
+        let Some(expr) = item.as_str() else {
+            return;
+        };
+        let source = template::condition_source(expr);
+        let prefix = template::CONDITION_PREFIX.len();
+        self.check_expression(
+            file,
+            item,
+            &source,
+            prefix,
+            "invalid `when` expression",
+            &scope,
+        );
+    }
+
+    /// Check the expression held by the string `item`, compiled into `source` at `prefix`
+    fn check_expression(
+        &mut self,
+        file: &SourceFile,
+        item: &Item,
+        source: &str,
+        prefix: usize,
+        message: &str,
+        scope: &Scope,
+    ) {
+        let (Some(expr), Some(span)) = (item.as_str(), item.span()) else {
+            return;
+        };
+        let start = file.string_start(&span);
+        if let Err((reason, span)) = compile(source) {
+            // errors point into the wrapping template
+            let span = span.map(|s| {
+                let from = s.start.saturating_sub(prefix).min(expr.len());
+                let to = s.end.saturating_sub(prefix).clamp(from, expr.len());
+                start + from..start + to
+            });
+            self.error(file, span, message, reason);
+            return;
+        }
+        self.check_refs(file, start, expr, &tokens(expr), scope, &[]);
+    }
+
+    /// Check the expression computing choices, or the shortcut keys of listed `expand` choices
+    fn check_choices(
+        &mut self,
+        file: &SourceFile,
+        choices: &Choices,
+        item: &Item,
+        expand: bool,
+        scope: Scope,
+    ) {
+        let choices = match (choices, item.as_str()) {
+            (Choices::Listed(choices), _) => choices,
+            (Choices::Computed(_), Some(expr)) => {
+                let (source, _) = template::expression_source(expr);
+                let prefix = source.find(expr).unwrap_or_default();
+                self.check_expression(
+                    file,
+                    item,
+                    &source,
+                    prefix,
+                    "invalid `choices` expression",
+                    &scope,
+                );
+                return;
+            }
+            (Choices::Computed(_), None) => return,
+        };
+        if !expand {
+            return;
+        }
+
+        // labels of the choices that can be picked
+        let labels = choices
+            .iter()
+            .zip(element_spans(item))
+            .filter_map(|(choice, span)| match choice {
+                Choice::Plain(text) if text != manifest::SEPARATOR => Some((text.as_str(), span)),
+                Choice::Table(table) if !table.disabled => Some((table.label.as_str(), span)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let texts = labels.iter().map(|(text, _)| *text).collect::<Vec<_>>();
+        if let Err(choice) = manifest::expand_keys(&texts) {
+            let index = texts.iter().position(|text| *text == choice).unwrap();
+            self.error(
+                file,
+                labels[index].1.clone(),
+                format!("no shortcut key left for choice `{}`", choice),
+                "every letter and digit of it is taken by an earlier choice or `h`",
+            );
+        }
+    }
+
+    fn check_validate(&mut self, file: &SourceFile, item: &Item) {
+        let Some(pattern) = item.as_str() else {
+            return;
+        };
+        if let Err(e) = regex::Regex::new(pattern) {
+            // the last line of a regex error holds its reason
+            let reason = e.to_string();
+            let reason = reason.lines().last().unwrap_or_default();
+            self.error(
+                file,
+                item.span(),
+                "invalid `validate` expression",
+                reason.trim_start_matches("error: "),
+            );
+        }
+    }
+
+    fn check_generators(
+        &mut self,
+        file: &SourceFile,
+        generators: &BTreeMap<String, manifest::Generator>,
+        table: &dyn TableLike,
+        manifest: &Manifest,
+    ) -> Vec<Generator> {
+        let mut checked = Vec::new();
+        for (name, generator) in generators {
+            let Some(raw) = table.get(name).and_then(Item::as_table_like) else {
+                continue;
+            };
+            self.check_fields::<manifest::Generator>(file, raw, "for a generator");
+            let keys = match raw.get("questions").and_then(tables) {
+                Some(questions) => {
+                    let questions = generator
+                        .questions
+                        .iter()
+                        .zip(questions)
+                        .collect::<Vec<_>>();
+                    self.check_questions(file, &questions, &manifest.keys, manifest.bases)
+                }
+                None => Vec::new(),
+            };
+            let scope = Scope {
+                roots: GENERATOR_ROOTS,
+                answers: manifest.keys.iter().chain(&keys).cloned().collect(),
+                variables: manifest.variables.clone(),
+                bases: manifest.bases,
+            };
+
+            let files = raw.get("files").and_then(tables).unwrap_or_default();
+            if files.is_empty() {
+                self.error(
+                    file,
+                    key_span(table, name),
+                    format!("generator `{}` has no files", name),
+                    "expected [[generators.<name>.files]] sections",
+                );
+            }
+            for (entry, (raw_entry, _)) in generator.files.iter().zip(files) {
+                self.check_fields::<GeneratorFile>(file, raw_entry, "for a generator file");
+                let path = self.root.join("_generators").join(name).join(&entry.source);
+                if !path.is_file() {
+                    self.error(
+                        file,
+                        raw_entry.get("source").and_then(Item::span),
+                        format!("generator file `{}` not found", entry.source),
+                        format!("expected at _generators/{}/{}", name, entry.source),
+                    );
+                }
+                // output paths are rendered like files
+                if let Some(span) = raw_entry.get("path").and_then(Item::span) {
+                    self.check_template(file, &entry.path, file.string_start(&span), &scope);
+                }
+            }
+
+            checked.push(Generator {
+                name: name.clone(),
+                keys,
+            });
+        }
+
+        checked
+    }
+
+    /// Check a template found at `offset` in `file`
+    fn check_template(&mut self, file: &SourceFile, source: &str, offset: usize, scope: &Scope) {
+        if let Err((reason, span)) = compile(source) {
+            let span = span.map(|s| offset + s.start..offset + s.end);
+            self.error(file, span, "invalid template syntax", reason);
+            return;
+        }
+
+        // names bound by the `for` and `with` blocks currently open
+        let mut locals = Vec::<Vec<&str>>::new();
+        for block in blocks(source) {
+            let offset = offset + block.offset;
+            let tokens = tokens(block.text);
+            if !block.statement {
+                self.check_refs(file, offset, block.text, &tokens, scope, &locals);
+                continue;
+            }
+
+            let keyword = match tokens.first() {
+                Some((_, Token::Ident(keyword))) => *keyword,
+                _ => continue,
+            };
+            let find = |word: &str| tokens.iter().position(|(_, t)| *t == Token::Ident(word));
+            match keyword {
+                "for" => {
+                    let split = find("in").unwrap_or(tokens.len());
+                    let expr = &tokens[split.min(tokens.len())..];
+                    self.check_refs(file, offset, block.text, expr, scope, &locals);
+                    let mut frame = idents(&tokens[1..split]);
+                    frame.push("loop");
+                    locals.push(frame);
+                }
+                "with" => {
+                    let split = find("as").unwrap_or(tokens.len());
+                    self.check_refs(file, offset, block.text, &tokens[1..split], scope, &locals);
+                    locals.push(idents(&tokens[split.min(tokens.len())..]));
+                }
+                "endfor" | "endwith" => {
+                    locals.pop();
+                }
+                "include" => {
+                    if let Some((at, Token::Str(name))) = tokens.get(1) {
+                        if !self.partials.iter().any(|p| p == name) {
+                            let span = Some(offset + at..offset + at + name.len() + 2);
+                            let message = format!("unknown partial `{}`", name);
+                            let label = "no such file in _partials";
+                            if scope.bases {
+                                self.warn(file, span, message, label);
+                            } else {
+                                self.error(file, span, message, label);
+                            }
+                        }
+                    }
+                    if let Some(split) = find("with") {
+                        self.check_refs(file, offset, block.text, &tokens[split..], scope, &locals);
+                    }
+                }
+                _ => self.check_refs(file, offset, block.text, &tokens, scope, &locals),
+            }
+        }
+    }
+
+    /// Check the variables and functions an expression refers to
+    fn check_refs(
+        &mut self,
+        file: &SourceFile,
+        offset: usize,
+        text: &str,
+        tokens: &[(usize, Token)],
+        scope: &Scope,
+        locals: &[Vec<&str>],
+    ) {
+        for (i, (at, token)) in tokens.iter().enumerate() {
+            let Token::Ident(name) = *token else {
+                continue;
+            };
+            let span = Some(offset + at..offset + at + name.len());
+            match i.checked_sub(1).map(|i| &tokens[i].1) {
+                // a member of a value
+                Some(Token::Punct('.')) => continue,
+                Some(Token::Punct('|')) => {
+                    let mut functions = template::FORMATTERS.iter().chain(template::FILTERS);
+                    if !functions.any(|f| *f == name) {
+                        self.error(
+                            file,
+                            span,
+                            format!("unknown formatter or filter `{}`", name),
+                            format!(
+                                "expected one of {}, {}",
+                                template::FORMATTERS.join(", "),
+                                template::FILTERS.join(", ")
+                            ),
+                        );
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            if KEYWORDS.contains(&name) || locals.iter().flatten().any(|l| *l == name) {
+                continue;
+            }
+            if !scope.roots.contains(&name) {
+                self.error(
+                    file,
+                    span,
+                    format!("undefined variable `{}`", name),
+                    format!("expected one of {}", scope.roots.join(", ")),
+                );
+                continue;
+            }
+
+            // `cpr.<key>` must be the key of a question and `vars.<name>` a variable
+            if let (Some((_, Token::Punct('.'))), Some((key_at, Token::Ident(key)))) =
+                (tokens.get(i + 1), tokens.get(i + 2))
+            {
+                let (names, what, label) = match name {
+                    "cpr" => (&scope.answers, "answer", "no question has the key"),
+                    variables::ROOT => (&scope.variables, "variable", "no variable is named"),
+                    _ => continue,
+                };
+                if !names.iter().any(|n| n == key) {
+                    let span = Some(offset + at..offset + key_at + key.len());
+                    let reference = &text[*at..key_at + key.len()];
+                    let message = format!("undefined {} `{}`", what, reference);
+                    let label = format!("{} `{}`", label, key);
+                    // questions and variables may come from the templates this one builds on
+                    if scope.bases {
+                        self.warn(file, span, message, label);
+                    } else {
+                        self.error(file, span, message, label);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A table along with its location in `cpr.toml`
+type SpannedTable<'a> = (&'a dyn TableLike, Option<Range<usize>>);
+
+/// Tables of an array of tables, written either as sections or as an inline array
+fn tables(item: &Item) -> Option<Vec<SpannedTable<'_>>> {
+    match item {
+        Item::ArrayOfTables(array) => Some(
+            array
+                .iter()
+                .map(|table| (table as &dyn TableLike, table.span()))
+                .collect(),
+        ),
+        Item::Value(Value::Array(array)) => array
+            .iter()
+            .map(|value| {
+                value
+                    .as_inline_table()
+                    .map(|table| (table as &dyn TableLike, value.span()))
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Locations of the elements of an array, written either as sections or inline
+fn element_spans(item: &Item) -> Vec<Option<Range<usize>>> {
+    match item {
+        Item::ArrayOfTables(array) => array.iter().map(|table| table.span()).collect(),
+        Item::Value(Value::Array(array)) => array.iter().map(|value| value.span()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Fields of `table` that deserializing it as `T` ignores
+///
+/// serde can't deny unknown fields next to a flattened enum, like the type of a question. Each
+/// field is given in turn a value no field accepts instead, the table still deserializing only
+/// if the field is ignored.
+fn ignored_fields<T: DeserializeOwned>(table: &dyn TableLike) -> Vec<&str> {
+    let probe = "1979-05-27"
+        .parse::<Value>()
+        .expect("a date is a valid value");
+    table
+        .iter()
+        .map(|(field, _)| field)
+        .filter(|field| {
+            let mut probed = inline_table(table);
+            probed.insert(*field, probe.clone());
+            T::deserialize(Value::InlineTable(probed).into_deserializer()).is_ok()
+        })
+        .collect()
+}
+
+/// `table` as a value to deserialize, keeping the locations of its values
+fn inline_table(table: &dyn TableLike) -> InlineTable {
+    let mut inline = InlineTable::new();
+    for (key, item) in table.iter() {
+        if let Ok(value) = item.clone().into_value() {
+            inline.insert(key, value);
+        }
+    }
+    inline
+}
+
+fn key_span(table: &dyn TableLike, key: &str) -> Option<Range<usize>> {
+    table.key(key).and_then(|key| key.span())
+}
+
+/// Names partials of the template at `root` are included by
+fn partial_names(root: &Path) -> Vec<String> {
+    let partials_dir = root.join("_partials");
+    walkdir::WalkDir::new(&partials_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_type().is_dir())
+        .map(|e| {
+            e.path()
+                .strip_prefix(&partials_dir)
+                .unwrap()
+                .with_extension("")
+                .to_string_lossy()
+                .replace('\\', "/")
+        })
+        .collect()
+}
+
+/// Compile a template, returning the reason and span of a syntax error
+fn compile(source: &str) -> Result<(), (String, Option<Range<usize>>)> {
+    let engine = template::engine();
+    match engine.compile(source) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(error_span(&e, source)),
+    }
+}
+
+/// Reason and span of a template error, recovered from its pretty output
+/// ` --> name:line:column` and the `^^^` underline, upon keeping the span itself private
+///
+/// upon is pinned to the version whose output this reads, which a test asserts.
+fn error_span(err: &upon::Error, source: &str) -> (String, Option<Range<usize>>) {
+    let pretty = format!("{:#}", err);
+    let reason = pretty
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("= reason: "))
+        .map(String::from)
+        .unwrap_or_else(|| err.to_string());
+
+    let location = pretty
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("--> "))
+        .and_then(|location| {
+            let mut parts = location.rsplitn(3, ':');
+            let column = parts.next()?.parse::<usize>().ok()?;
+            let line = parts.next()?.parse::<usize>().ok()?;
+            Some((line, column))
+        });
+    let width = pretty
+        .lines()
+        .rev()
+        .find(|line| line.contains('^'))
+        .map_or(1, |line| line.matches('^').count());
+
+    let span = location.map(|(line, column)| {
+        // upon subtracts the display width of the lines before the error from its byte offset,
+        // which is undone here taking every printed character as one column wide
+        let before = source
+            .split_terminator('\n')
+            .take(line.saturating_sub(1))
+            .map(|line| line.chars().count() + 1)
+            .sum::<usize>();
+        let mut start = (before + column.saturating_sub(1)).min(source.len());
+        while !source.is_char_boundary(start) {
+            start -= 1;
+        }
+        // errors at the end of the template point at its last character
+        if start == source.len() {
+            start = source.char_indices().last().map_or(0, |(i, _)| i);
+        }
+        let end = source[start..]
+            .char_indices()
+            .nth(width)
+            .map_or(source.len(), |(i, _)| start + i);
+        start..end
+    });
+    (reason, span)
+}
+
+/// An expression `{{ ... }}` or statement `{% ... %}` block of a template
+struct Block<'a> {
+    /// Offset of the contents of the block in the template
+    offset: usize,
+    text: &'a str,
+    statement: bool,
+}
+
+fn blocks(source: &str) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    let mut rest = 0;
+    while let Some(start) = source[rest..].find('{').map(|i| rest + i) {
+        let close = match source.get(start + 1..start + 2) {
+            Some("{") => "}}",
+            Some("%") => "%}",
+            Some("#") => "#}",
+            _ => {
+                rest = start + 1;
+                continue;
+            }
+        };
+        let Some(end) = source[start + 2..].find(close).map(|i| start + 2 + i) else {
+            break;
+        };
+        if close != "#}" {
+            blocks.push(Block {
+                offset: start + 2,
+                text: &source[start + 2..end],
+                statement: close == "%}",
+            });
+        }
+        rest = end + 2;
+    }
+    blocks
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Ident(&'a str),
+    Str(&'a str),
+    Number,
+    Punct(char),
+}
+
+/// Tokens of a block along with their offsets in it
+fn tokens(text: &str) -> Vec<(usize, Token<'_>)> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c.is_alphabetic() || c == '_' {
+            let mut end = i + c.len_utf8();
+            while let Some(&(j, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = j + c.len_utf8();
+                chars.next();
+            }
+            tokens.push((i, Token::Ident(&text[i..end])));
+        } else if c.is_ascii_digit() {
+            while chars
+                .peek()
+                .is_some_and(|(_, c)| c.is_alphanumeric() || *c == '_')
+            {
+                chars.next();
+            }
+            tokens.push((i, Token::Number));
+        } else if c == '"' {
+            let mut end = text.len();
+            let mut escaped = false;
+            for (j, c) in chars.by_ref() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => {
+                        end = j;
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            tokens.push((i, Token::Str(&text[i + 1..end])));
+        } else if !c.is_whitespace() {
+            tokens.push((i, Token::Punct(c)));
+        }
+    }
+    tokens
+}
+
+fn idents<'a>(tokens: &[(usize, Token<'a>)]) -> Vec<&'a str> {
+    tokens
+        .iter()
+        .filter_map(|(_, token)| match token {
+            Token::Ident(name) => Some(*name),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reason and text pointed at by the syntax error of `source`
+    fn syntax_error(source: &str) -> (String, &str) {
+        let (reason, span) = compile(source).unwrap_err();
+        (reason, &source[span.expect("the error has a location")])
+    }
+
+    #[test]
+    fn reports_every_broken_question() {
+        let root = tempfile::tempdir().unwrap();
+        let manifest = r#"
+            [[questions]]
+            key = "a"
+            message = "A?"
+            type = "nope"
+
+            [[questions]]
+            key = "b"
+            type = "input"
+
+            [[questions]]
+            key = "c"
+            message = "C?"
+            type = "confirm"
+            when = "cpr.b"
+            "#;
+        std::fs::write(root.path().join("cpr.toml"), manifest).unwrap();
+        std::fs::write(root.path().join("file.txt"), "{{ cpr.a }} {{ cpr.c }}").unwrap();
+        let problems = lint(root.path()).unwrap();
+        // the answers of both questions are still known to the file
+        let labels = problems
+            .iter()
+            .map(|p| p.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels.len(), 2, "{:?}", labels);
+        assert!(labels[0].starts_with("unknown variant `nope`"));
+        assert_eq!(labels[1], "missing field `message`");
+    }
+
+    /// upon keeps the location of its errors private, `error_span` reads it from this output
+    #[test]
+    fn upon_error_output_is_as_error_span_reads_it() {
+        let e = template::engine()
+            .compile("{{ x }}\n{{ name | }}")
+            .unwrap_err();
+        assert_eq!(
+            format!("{:#}", e),
+            "invalid syntax\n\
+             \n  --> <anonymous>:2:11\
+             \n   |\
+             \n 2 | {{ name | }}\
+             \n   |           ^^-\
+             \n   |\
+             \n   = reason: expected identifier, found end expression\n"
+        );
+    }
+
+    #[test]
+    fn error_span_points_at_the_invalid_token() {
+        assert_eq!(
+            syntax_error("{{ name | }}"),
+            (
+                "expected identifier, found end expression".to_string(),
+                "}}"
+            )
+        );
+        assert_eq!(syntax_error("{{ name ! }}").1, "!");
+    }
+
+    #[test]
+    fn error_span_counts_the_lines_before_the_error() {
+        let source = "line one\n{% if cpr.tests %}\n{% endfor %}";
+        assert_eq!(
+            syntax_error(source),
+            ("unexpected `endfor` block".to_string(), "{% endfor %}")
+        );
+        assert_eq!(syntax_error("line one\r\nline two\r\n{{ x ! }}").1, "!");
+    }
+
+    #[test]
+    fn error_span_handles_multibyte_characters() {
+        assert_eq!(syntax_error("é {{ x ! }}").1, "!");
+        assert_eq!(syntax_error("ça\nété {{ x ! }}").1, "!");
+    }
+
+    #[test]
+    fn error_span_at_the_end_points_at_the_last_character() {
+        assert_eq!(syntax_error("{{ x").1, "x");
+        assert_eq!(syntax_error("{% if x %}").1, "{% if x %}");
+    }
+
+    #[test]
+    fn blocks_skip_comments_and_single_braces() {
+        let source = "{ {{ cpr.name }} {# {{ ignored }} #}\n{% if cpr.tests %}";
+        let blocks = blocks(source);
+        let found: Vec<_> = blocks
+            .iter()
+            .map(|block| (block.offset, block.text, block.statement))
+            .collect();
+        assert_eq!(
+            found,
+            [(4, " cpr.name ", false), (39, " if cpr.tests ", true)]
+        );
+    }
+
+    #[test]
+    fn blocks_stop_at_an_unclosed_block() {
+        let blocks = blocks("{{ a }} {% if b");
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].text, " a ");
+    }
+
+    #[test]
+    fn tokens_split_identifiers_strings_numbers_and_punctuation() {
+        assert_eq!(
+            tokens(r#"cpr.é_1 | replace: "a \" b", 12"#),
+            [
+                (0, Token::Ident("cpr")),
+                (3, Token::Punct('.')),
+                (4, Token::Ident("é_1")),
+                (9, Token::Punct('|')),
+                (11, Token::Ident("replace")),
+                (18, Token::Punct(':')),
+                (20, Token::Str(r#"a \" b"#)),
+                (28, Token::Punct(',')),
+                (30, Token::Number),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokens_keep_unterminated_strings() {
+        assert_eq!(
+            tokens(r#"x "rest"#),
+            [(0, Token::Ident("x")), (2, Token::Str("rest"))]
+        );
+    }
+}
@@ -1,14 +1,32 @@
 mod config;
+mod document;
 mod errors;
+mod extract;
 mod format;
+mod git;
+mod license;
+mod lint;
+mod manifest;
+mod migrate;
+mod project;
+mod registry;
+mod repo;
+mod scaffold;
+mod snapshot;
 mod subcommands;
+mod template;
+mod tls;
+mod variables;
 
 use clap::{Parser, Subcommand};
-use config::Config;
+use config::{Config, ConfigLayers};
 use miette::IntoDiagnostic;
 use simple_logger::SimpleLogger;
-use std::path::PathBuf;
-use subcommands::{init, new, prompt_project_info};
+use std::path::{Path, PathBuf};
+use subcommands::{
+    add, edit_config, extract_template, gen, info, init, lint_template, list, migrate_config, new,
+    new_template, prompt_project_info, search, test_template,
+};
 
 pub fn get_styles() -> clap::builder::Styles {
     clap::builder::Styles::styled()
@@ -55,7 +73,11 @@ struct Cli {
     #[command(subcommand)]
     command: Commands,
 
-    #[arg(short, long, help = "Global configuration file path")]
+    #[arg(
+        short,
+        long,
+        help = "User configuration file path, merged over the system configuration"
+    )]
     config: Option<PathBuf>,
 }
 
@@ -66,21 +88,177 @@ enum Commands {
     Init {
         /// Directory to target (ex. ./my_project)
         directory: PathBuf,
-        /// Repository path optionally including prefix (ex. gh:cpr-rs/cpp, cpr-rs/cpp)
+        /// Registry name or repository path optionally including prefix and ref (ex. cpp, gh:cpr-rs/cpp, cpr-rs/cpp@v1)
         repo_path: String,
     },
     /// Create a new project with a template
     #[command(arg_required_else_help = true)]
     New {
-        /// Repository path optionally including prefix (ex. gh:cpr-rs/cpp, cpr-rs/cpp)
+        /// Registry name or repository path optionally including prefix and ref (ex. cpp, gh:cpr-rs/cpp, cpr-rs/cpp@v1)
         repo_path: String,
     },
+    /// Apply an additional template to an existing project
+    #[command(arg_required_else_help = true)]
+    Add {
+        /// Registry name or repository path optionally including prefix and ref (ex. gtest, gh:cpr-rs/gtest)
+        repo_path: String,
+        /// Directory to render the template into, relative to the project root
+        #[arg(long, default_value = ".")]
+        into: PathBuf,
+    },
+    /// Run a generator from the project template (ex. cpr gen class net::Socket)
+    Gen {
+        /// Generator name (ex. class), lists the available generators if omitted
+        name: Option<String>,
+        /// Arguments passed to the generator (ex. net::Socket)
+        args: Vec<String>,
+    },
+    /// List the templates of every registry
+    List {
+        /// Fetch registry repositories again
+        #[arg(long)]
+        update: bool,
+    },
+    /// Search the registries for templates
+    #[command(arg_required_else_help = true)]
+    Search {
+        /// Term matched against names, descriptions, tags and languages
+        term: String,
+    },
+    /// Show details of a template without generating anything
+    #[command(arg_required_else_help = true)]
+    Info {
+        /// Template name or repository path (ex. cpp or gh:org/template)
+        repo_path: String,
+    },
+    /// Develop templates
+    #[command(arg_required_else_help = true)]
+    Template {
+        #[command(subcommand)]
+        command: TemplateCommands,
+    },
+    /// Inspect the merged configuration
+    #[command(arg_required_else_help = true)]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
     /// Set default git service
     #[command(arg_required_else_help = true)]
     Services {
         #[command(subcommand)]
         command: ServiceCommands,
     },
+    /// Manage template aliases
+    #[command(arg_required_else_help = true)]
+    Alias {
+        #[command(subcommand)]
+        command: AliasCommands,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum TemplateCommands {
+    /// Create a starter template with example questions, files and a test fixture
+    #[command(arg_required_else_help = true)]
+    New {
+        /// Directory to create the template in
+        directory: PathBuf,
+    },
+    /// Turn an existing project into a template
+    #[command(arg_required_else_help = true)]
+    Extract {
+        /// Project directory
+        project: PathBuf,
+        /// Name used throughout the project, replaced in every casing (ex. my_project)
+        #[arg(long)]
+        name_var: String,
+        /// Author to replace with `{{ author }}`
+        #[arg(long)]
+        author: Option<String>,
+        /// Year to replace with `{{ year }}`
+        #[arg(long)]
+        year: Option<i32>,
+        /// Directory to create the template in [default: <project>-template]
+        #[arg(long)]
+        into: Option<PathBuf>,
+    },
+    /// Check a template's cpr.toml and files for mistakes
+    Lint {
+        /// Template directory
+        #[arg(default_value = ".")]
+        path: PathBuf,
+    },
+    /// Render a template with the answer fixtures in `_tests/` and compare it with their snapshots
+    Test {
+        /// Template directory
+        #[arg(default_value = ".")]
+        path: PathBuf,
+        /// Replace the snapshots with the rendered output
+        #[arg(long)]
+        bless: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommands {
+    /// Show the configuration merged from every layer
+    Show {
+        /// Show the file or environment variable each value comes from
+        #[arg(long)]
+        origin: bool,
+    },
+    /// Print a merged value
+    #[command(arg_required_else_help = true)]
+    Get {
+        /// Dotted path of the value (ex. services.gh.url)
+        key: String,
+    },
+    /// Set a value in the user configuration
+    #[command(arg_required_else_help = true)]
+    Set {
+        /// Dotted path of the value (ex. services.gh.depth)
+        key: String,
+        /// Value, parsed as TOML when possible and as a string otherwise (ex. 1, true, gl)
+        value: String,
+    },
+    /// Remove a value from the user configuration
+    #[command(arg_required_else_help = true)]
+    Unset {
+        /// Dotted path of the value (ex. services.gh.depth)
+        key: String,
+    },
+    /// Open the user configuration in $EDITOR and validate it
+    Edit,
+    /// Check the configuration of every layer
+    Validate,
+    /// Upgrade the user configuration to the current format
+    Migrate {
+        /// Show the changes without saving them
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum AliasCommands {
+    /// Add a new alias
+    #[command(arg_required_else_help = true)]
+    Add {
+        /// Short name for the template (ex. lib)
+        name: String,
+        /// Repository path optionally including prefix, subdirectory and ref
+        /// (ex. gh:our-org/cpp-templates//lib-header-only@stable)
+        repo_path: String,
+    },
+    /// Remove an alias
+    #[command(arg_required_else_help = true)]
+    Remove {
+        /// Short name for the template (ex. lib)
+        name: String,
+    },
+    /// List available aliases
+    List,
 }
 
 #[derive(Debug, Subcommand)]
@@ -92,8 +270,12 @@ enum ServiceCommands {
         prefix: String,
         /// URL format for the git server (ex. https://github.com/{{ repo }}.git)
         /// The `{{ repo }}` placeholder will be replaced with the repository name
-        /// when creating a new project
+        /// when creating a new project, `{{ owner }}`, `{{ name }}`, `{{ group }}`,
+        /// `{{ ref }}` and `{{ user }}` are also available
         url: String,
+        /// User available as `{{ user }}` in the URL format
+        #[arg(long)]
+        user: Option<String>,
     },
     /// Remove a service
     #[command(arg_required_else_help = true)]
@@ -121,17 +303,22 @@ fn main() -> miette::Result<()> {
 
     let args = Cli::parse();
 
-    let config_path = args.config.unwrap_or_else(|| {
-        dirs::home_dir()
-            .map(|d| d.join(".cpr").join("config.toml"))
-            .expect("Could not determine home directory")
-    });
+    let config_path = args.config.unwrap_or_else(config::user_config_path);
     if !config_path.exists() {
         Config::init(&config_path)?;
         println!("Created default configuration at {:?}", config_path);
     }
 
-    let mut config = Config::from_file(&config_path)?;
+    // editing must work even when the configuration doesn't load
+    if let Commands::Config {
+        command: ConfigCommands::Edit,
+    } = args.command
+    {
+        return edit_config(&config_path);
+    }
+
+    let layers = ConfigLayers::load(&config_path)?;
+    let mut config = layers.config()?;
 
     match args.command {
         Commands::Init {
@@ -143,14 +330,79 @@ fn main() -> miette::Result<()> {
         Commands::New { repo_path } => {
             new(repo_path, prompt_project_info(&config)?)?;
         }
+        Commands::Add { repo_path, into } => {
+            add(into, repo_path, prompt_project_info(&config)?)?;
+        }
+        Commands::Gen { name, args } => {
+            gen(Path::new("."), name, args)?;
+        }
+        Commands::List { update } => {
+            list(&config, update)?;
+        }
+        Commands::Search { term } => {
+            search(&config, &term)?;
+        }
+        Commands::Info { repo_path } => {
+            info(&config, &repo_path)?;
+        }
+        Commands::Template { command } => match command {
+            TemplateCommands::New { directory } => {
+                new_template(&config, &directory)?;
+            }
+            TemplateCommands::Extract {
+                project,
+                name_var,
+                author,
+                year,
+                into,
+            } => {
+                extract_template(&config, &project, into, &name_var, author, year)?;
+            }
+            TemplateCommands::Lint { path } => {
+                lint_template(&path)?;
+            }
+            TemplateCommands::Test { path, bless } => {
+                test_template(&config, &path, bless)?;
+            }
+        },
+        Commands::Config { command } => match command {
+            ConfigCommands::Show { origin: false } => {
+                print!("{}", toml::to_string(&layers.table).into_diagnostic()?);
+            }
+            ConfigCommands::Show { origin: true } => {
+                for (path, value) in layers.values() {
+                    println!("{} = {}  # {}", path, value, layers.origins[&path]);
+                }
+            }
+            ConfigCommands::Get { key } => match layers.get(&key)? {
+                toml::Value::String(value) => println!("{}", value),
+                toml::Value::Table(table) => {
+                    print!("{}", toml::to_string(table).into_diagnostic()?)
+                }
+                value => println!("{}", value),
+            },
+            ConfigCommands::Set { key, value } => {
+                layers.set(&key, &value)?;
+            }
+            ConfigCommands::Unset { key } => {
+                layers.unset(&key)?;
+            }
+            ConfigCommands::Edit => unreachable!("handled before loading the configuration"),
+            ConfigCommands::Validate => {
+                layers.validate()?;
+                println!("Configuration is valid");
+            }
+            ConfigCommands::Migrate { dry_run } => {
+                migrate_config(&config_path, dry_run)?;
+            }
+        },
+        // changes only ever go to the user configuration file
         Commands::Services { command } => match command {
-            ServiceCommands::Add { prefix, url } => {
-                config.add_service(prefix, url)?;
-                config.write(&config_path)?;
+            ServiceCommands::Add { prefix, url, user } => {
+                layers.edit(|c| c.add_service(prefix, url, user))?;
             }
             ServiceCommands::Remove { prefix } => {
-                config.remove_service(&prefix)?;
-                config.write(&config_path)?;
+                layers.edit(|c| c.remove_service(&prefix))?;
             }
             ServiceCommands::List => {
                 config.services.iter().for_each(|(prefix, base_url)| {
@@ -158,8 +410,8 @@ fn main() -> miette::Result<()> {
                 });
             }
             ServiceCommands::Default { prefix } => {
-                if let Some(prefix) = prefix {
-                    config.set_default_service(&prefix)?;
+                let prefix = if let Some(prefix) = prefix {
+                    prefix
                 } else {
                     let service = requestty::Question::select("service")
                         .message("Select the default service")
@@ -174,9 +426,27 @@ fn main() -> miette::Result<()> {
                     // the prefix is formatted as `prefix` -> url, so we need to extract the prefix
                     // this is a bit hacky, but it works
                     let prefix = prefix.as_list_item().unwrap().text.split('`').nth(1).unwrap();
-                    config.set_default_service(prefix)?;
-                }
-                config.write(&config_path)?;
+                    prefix.to_string()
+                };
+                // the service may come from any layer, check it against the merged configuration
+                config.set_default_service(&prefix)?;
+                layers.edit(|c| {
+                    c.default_service = prefix;
+                    Ok(())
+                })?;
+            }
+        },
+        Commands::Alias { command } => match command {
+            AliasCommands::Add { name, repo_path } => {
+                layers.edit(|c| c.add_alias(name, repo_path))?;
+            }
+            AliasCommands::Remove { name } => {
+                layers.edit(|c| c.remove_alias(&name))?;
+            }
+            AliasCommands::List => {
+                config.aliases.iter().for_each(|(name, repo_path)| {
+                    println!("`{}`: {}", name, repo_path);
+                });
             }
         },
     }
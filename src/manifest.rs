@@ -0,0 +1,300 @@
+use miette::{NamedSource, SourceSpan};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Choice of a select question rendered as a separator instead of an option
+pub const SEPARATOR: &str = "cpr_sep";
+
+/// Contents of a template's `cpr.toml`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TemplateManifest {
+    /// `[template]` section describing the template
+    #[serde(default)]
+    pub template: TemplateInfo,
+    /// Template this one is built on, rendered before it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<Layer>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub questions: Vec<TemplateQuestion>,
+    /// Expressions evaluated after prompting, available as `vars.<name>`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+    /// Generators whose files live in `_generators/<name>/`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub generators: BTreeMap<String, Generator>,
+    /// Keys this version of cpr doesn't know, `cpr template lint` reports them
+    #[serde(flatten)]
+    pub extra: toml::Table,
+}
+
+impl TemplateManifest {
+    pub fn parse(input: &str) -> Result<Self, ManifestError> {
+        toml::from_str(input).map_err(|e| ManifestError::from_serde_error(input, e))
+    }
+}
+
+/// Metadata of a template, shown by `cpr info`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TemplateInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Oldest version of cpr able to render the template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_cpr_version: Option<semver::Version>,
+    /// Newest version of cpr able to render the template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cpr_version: Option<semver::Version>,
+}
+
+impl TemplateInfo {
+    /// Range of cpr versions the template supports, `None` if it supports all of them
+    pub fn cpr_versions(&self) -> Option<String> {
+        match (&self.min_cpr_version, &self.max_cpr_version) {
+            (Some(min), Some(max)) => Some(format!(">= {}, <= {}", min, max)),
+            (Some(min), None) => Some(format!(">= {}", min)),
+            (None, Some(max)) => Some(format!("<= {}", max)),
+            (None, None) => None,
+        }
+    }
+
+    /// Whether `version` of cpr is within the supported range, both ends included
+    pub fn supports(&self, version: &semver::Version) -> bool {
+        self.min_cpr_version
+            .as_ref()
+            .map_or(true, |min| version >= min)
+            && self
+                .max_cpr_version
+                .as_ref()
+                .map_or(true, |max| version <= max)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Layer {
+    /// Repository path of the layered template
+    pub repo: String,
+}
+
+/// A question asked before rendering, its answer is available as `cpr.<key>`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateQuestion {
+    pub key: String,
+    pub message: String,
+    /// Condition on earlier answers, the question is skipped unless it holds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+    #[serde(flatten)]
+    pub kind: QuestionKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QuestionKind {
+    Confirm,
+    Input {
+        /// Regular expression the answer must match
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        validate: Option<String>,
+    },
+    /// Text hidden while typed, kept out of logs, errors and `.cpr/answers.toml`
+    Password {
+        /// Regular expression the answer must match
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        validate: Option<String>,
+    },
+    /// Text written in the editor of the user
+    Editor {
+        /// Extension of the file opened in the editor, e.g. `.md`, for syntax highlighting
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        extension: Option<String>,
+    },
+    Int,
+    Float,
+    /// Select an SPDX license, whose text is written to `LICENSE`
+    License,
+    Select {
+        choices: Choices,
+    },
+    MultiSelect {
+        choices: Choices,
+    },
+    OrderSelect {
+        choices: Choices,
+    },
+    /// One of the `choices`, picked with a single key
+    Expand {
+        choices: Choices,
+    },
+    /// One of the `choices`, picked by its number
+    RawSelect {
+        choices: Choices,
+    },
+    /// Entries answering the nested `questions`, added until the user stops
+    List {
+        questions: Vec<TemplateQuestion>,
+    },
+}
+
+/// Choices of a select question, listed or computed from the answers before it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    untagged,
+    expecting = "choices as an array of strings, `{ label = ... }` and `{ separator = ... }` \
+                 tables, or as a string expression"
+)]
+pub enum Choices {
+    Listed(Vec<Choice>),
+    /// Expression evaluating to a list of strings or tables shaped like a [`ChoiceTable`]
+    Computed(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Choice {
+    /// Label and value at once, `cpr_sep` being a separator
+    Plain(String),
+    Separator(SeparatorTable),
+    Table(ChoiceTable),
+}
+
+/// Separator between choices, an empty text draws a line
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeparatorTable {
+    pub separator: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChoiceTable {
+    /// Text shown to the user
+    pub label: String,
+    /// Answer stored when the choice is picked, the label if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<toml::Value>,
+    /// Shown next to the label
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Shown but can't be picked
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled: bool,
+}
+
+impl QuestionKind {
+    /// Choices of the question, if it picks among them
+    pub fn choices(&self) -> Option<&Choices> {
+        match self {
+            QuestionKind::Select { choices }
+            | QuestionKind::MultiSelect { choices }
+            | QuestionKind::OrderSelect { choices }
+            | QuestionKind::Expand { choices }
+            | QuestionKind::RawSelect { choices } => Some(choices),
+            _ => None,
+        }
+    }
+}
+
+impl TemplateQuestion {
+    /// Whether the answer must never be shown, lists holding a secret being hidden whole
+    pub fn is_secret(&self) -> bool {
+        match &self.kind {
+            QuestionKind::Password { .. } => true,
+            QuestionKind::List { questions } => questions.iter().any(TemplateQuestion::is_secret),
+            _ => false,
+        }
+    }
+}
+
+/// Shortcut keys of the choices of an `expand` question, given the labels of the choices that
+/// can be picked
+///
+/// Each choice gets the first of its letters or digits no earlier choice took, `h` being
+/// reserved for help. Fails with the first choice left without a key.
+pub fn expand_keys<'a>(labels: &[&'a str]) -> Result<Vec<char>, &'a str> {
+    let mut keys = Vec::<char>::new();
+    for label in labels {
+        let key = label
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .find(|c| *c != 'h' && !keys.contains(c))
+            .ok_or(*label)?;
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+/// A generator run by `cpr gen <name>` in projects created from the template
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Generator {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Questions asked on top of the answers the project was created with
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub questions: Vec<TemplateQuestion>,
+    #[serde(default)]
+    pub files: Vec<GeneratorFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeneratorFile {
+    /// File in the generator directory
+    pub source: String,
+    /// Path template of the generated file, relative to the project root
+    pub path: String,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[error("malformed cpr.toml in template")]
+pub struct ManifestError {
+    message: String,
+    #[source_code]
+    input: NamedSource<String>,
+    #[label("{message}")]
+    location: SourceSpan,
+}
+
+impl ManifestError {
+    pub fn from_serde_error(input: &str, cause: toml::de::Error) -> Self {
+        Self {
+            message: cause.message().to_string(),
+            input: NamedSource::new("cpr.toml", input.to_string()),
+            location: SourceSpan::from(cause.span().unwrap_or_default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_keys_take_the_first_free_letter_or_digit() {
+        assert_eq!(
+            expand_keys(&["Debug", "Release", "RelWithDebInfo", "C++20"]),
+            Ok(vec!['d', 'r', 'e', 'c'])
+        );
+        assert_eq!(
+            expand_keys(&["Help", "hash", "42"]),
+            Ok(vec!['e', 'a', '4'])
+        );
+    }
+
+    #[test]
+    fn expand_keys_fail_with_the_first_choice_left_without_key() {
+        assert_eq!(expand_keys(&["ab", "ba", "hab", "b"]), Err("hab"));
+        assert_eq!(expand_keys(&["--"]), Err("--"));
+    }
+}
@@ -0,0 +1,49 @@
+use crate::config::ConfigErrorKind;
+use miette::{IntoDiagnostic, Result};
+use toml_edit::DocumentMut;
+
+/// Version of the configuration format written by this version of cpr
+pub const CONFIG_VERSION: i64 = 1;
+
+/// Upgrade a document from the version at its index to the next one, returning a description
+/// of each change
+type Migration = fn(&mut DocumentMut) -> Vec<String>;
+
+const MIGRATIONS: [Migration; CONFIG_VERSION as usize] = [v0_to_v1];
+
+/// Files written before the format was versioned only lack the `version` key
+fn v0_to_v1(_doc: &mut DocumentMut) -> Vec<String> {
+    Vec::new()
+}
+
+/// Version of a configuration document, files without a `version` key predate versioning
+pub fn version(doc: &DocumentMut) -> Result<i64> {
+    match doc.get("version") {
+        None => Ok(0),
+        Some(item) => item
+            .as_integer()
+            .filter(|v| *v >= 0)
+            .ok_or_else(|| ConfigErrorKind::InvalidVersion(item.to_string().trim().to_string()))
+            .into_diagnostic(),
+    }
+}
+
+/// Upgrade a configuration document to the current version in place, keeping comments and
+/// keys cpr doesn't know about
+pub fn migrate(doc: &mut DocumentMut) -> Result<Vec<String>> {
+    let from = version(doc)?;
+    if from > CONFIG_VERSION {
+        return Err(ConfigErrorKind::UnsupportedVersion(from, CONFIG_VERSION)).into_diagnostic();
+    }
+
+    let mut changes = Vec::new();
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        log::debug!("migrating config from version {}", version);
+        changes.extend(migration(doc));
+    }
+    if from < CONFIG_VERSION {
+        doc["version"] = toml_edit::value(CONFIG_VERSION);
+        changes.push(format!("set `version` from {} to {}", from, CONFIG_VERSION));
+    }
+    Ok(changes)
+}
@@ -0,0 +1,250 @@
+use crate::config::ConfigError;
+use crate::manifest::Generator;
+use miette::{IntoDiagnostic, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Directory holding cpr metadata inside a generated project
+pub const PROJECT_DIR: &str = ".cpr";
+
+fn read_toml<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    log::debug!("reading project metadata from file: {:?}", path);
+    let input = std::fs::read_to_string(path).into_diagnostic()?;
+    toml::from_str(&input)
+        .map_err(|e| ConfigError::from_serde_error(input, e).into())
+}
+
+fn write_toml<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap()).into_diagnostic()?;
+    let toml = toml::to_string(value).into_diagnostic()?;
+    std::fs::write(path, toml).into_diagnostic()
+}
+
+/// Record of the template layers added to a project with `cpr add`, kept in `.cpr/layers.toml`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LayerRecords {
+    #[serde(default)]
+    pub layers: Vec<LayerRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LayerRecord {
+    /// Repository path the layer was fetched from
+    pub repo: String,
+    /// Date the layer was added
+    pub added: String,
+    /// Files written by the layer, relative to the project root
+    pub files: Vec<PathBuf>,
+    /// Files that already existed and were kept
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<PathBuf>,
+}
+
+impl LayerRecords {
+    pub fn from_project(project: &Path) -> Result<Self> {
+        read_toml(&project.join(PROJECT_DIR).join("layers.toml"))
+    }
+
+    pub fn write(&self, project: &Path) -> Result<()> {
+        write_toml(&project.join(PROJECT_DIR).join("layers.toml"), self)
+    }
+}
+
+/// Generators installed by the project template, kept in `.cpr/generators.toml`
+///
+/// Uses the same `[generators.<name>]` format as `cpr.toml`, the files of each generator are
+/// copied to `.cpr/generators/<name>/`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Generators {
+    #[serde(default)]
+    pub generators: BTreeMap<String, Generator>,
+}
+
+impl Generators {
+    pub fn from_project(project: &Path) -> Result<Self> {
+        read_toml(&project.join(PROJECT_DIR).join("generators.toml"))
+    }
+
+    pub fn write(&self, project: &Path) -> Result<()> {
+        write_toml(&project.join(PROJECT_DIR).join("generators.toml"), self)
+    }
+
+    pub fn files_dir(project: &Path, name: &str) -> PathBuf {
+        project.join(PROJECT_DIR).join("generators").join(name)
+    }
+}
+
+/// Answers the project was rendered with, kept in `.cpr/answers.toml`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct AnswersFile {
+    /// Paths of the answers left empty, e.g. `cpr.libraries[1].include_dir`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    skipped: Vec<String>,
+    #[serde(flatten)]
+    answers: toml::Table,
+}
+
+/// Read the answers the project was rendered with from `.cpr/answers.toml`
+pub fn read_answers(project: &Path) -> Result<upon::Value> {
+    let file: AnswersFile = read_toml(&project.join(PROJECT_DIR).join("answers.toml"))?;
+    let mut answers = upon::to_value(file.answers).into_diagnostic()?;
+    // skipped answers are empty rather than missing, so templates can still test them
+    for path in &file.skipped {
+        let path = path.replace('[', ".").replace(']', "");
+        restore_none(&mut answers, &path.split('.').collect::<Vec<_>>());
+    }
+    Ok(answers)
+}
+
+/// Save the context the project was rendered with so generators can reuse it
+pub fn write_answers(project: &Path, context: &upon::Value) -> Result<()> {
+    let mut answers = match context {
+        upon::Value::Map(map) => map.clone(),
+        _ => BTreeMap::new(),
+    };
+    // the year is taken from the day a generator runs
+    answers.remove("year");
+    let mut skipped = Vec::new();
+    remove_none(&mut answers, "", &mut skipped);
+    let file = AnswersFile {
+        skipped,
+        answers: toml::Table::try_from(answers).into_diagnostic()?,
+    };
+    write_toml(&project.join(PROJECT_DIR).join("answers.toml"), &file)
+}
+
+/// TOML has no empty value, so unanswered questions are left out, within `list` entries too,
+/// and their path is added to `skipped`
+fn remove_none(map: &mut BTreeMap<String, upon::Value>, prefix: &str, skipped: &mut Vec<String>) {
+    map.retain(|key, value| {
+        let empty = *value == upon::Value::None;
+        if empty {
+            skipped.push(format!("{}{}", prefix, key));
+        }
+        !empty
+    });
+    for (key, value) in map.iter_mut() {
+        remove_nested_none(value, &format!("{}{}", prefix, key), skipped);
+    }
+}
+
+fn remove_nested_none(value: &mut upon::Value, path: &str, skipped: &mut Vec<String>) {
+    match value {
+        upon::Value::Map(map) => remove_none(map, &format!("{}.", path), skipped),
+        upon::Value::List(list) => {
+            // paths use the indices before removal, restoring them in order undoes it
+            for (i, value) in list.iter_mut().enumerate() {
+                let path = format!("{}[{}]", path, i);
+                if *value == upon::Value::None {
+                    skipped.push(path);
+                } else {
+                    remove_nested_none(value, &path, skipped);
+                }
+            }
+            list.retain(|value| *value != upon::Value::None);
+        }
+        _ => {}
+    }
+}
+
+/// Put back the empty answer removed from `path` by `remove_none`
+fn restore_none(value: &mut upon::Value, path: &[&str]) {
+    match (value, path) {
+        (upon::Value::Map(map), [key]) => {
+            map.insert(key.to_string(), upon::Value::None);
+        }
+        (upon::Value::Map(map), [key, rest @ ..]) => {
+            if let Some(value) = map.get_mut(*key) {
+                restore_none(value, rest);
+            }
+        }
+        (upon::Value::List(list), [index, rest @ ..]) => {
+            let Ok(index) = index.parse::<usize>() else {
+                return;
+            };
+            if rest.is_empty() && index <= list.len() {
+                list.insert(index, upon::Value::None);
+            } else if let Some(value) = list.get_mut(index) {
+                restore_none(value, rest);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library(name: &str, include_dir: upon::Value) -> upon::Value {
+        upon::value! { name: name, include_dir: include_dir }
+    }
+
+    /// Answers with a skipped question, at the top level and within a `list` entry
+    fn answers() -> upon::Value {
+        upon::value! {
+            project: { name: "demo" },
+            cpr: {
+                skipped: upon::Value::None,
+                libraries: upon::Value::List(vec![
+                    library("core", upon::Value::from("include/core")),
+                    library("detail", upon::Value::None),
+                ]),
+            },
+        }
+    }
+
+    #[test]
+    fn removes_unanswered_questions_within_list_entries() {
+        let upon::Value::Map(mut answers) = answers() else {
+            unreachable!()
+        };
+        let mut skipped = Vec::new();
+        remove_none(&mut answers, "", &mut skipped);
+        assert_eq!(
+            upon::Value::Map(answers),
+            upon::value! {
+                project: { name: "demo" },
+                cpr: {
+                    libraries: upon::Value::List(vec![
+                        library("core", upon::Value::from("include/core")),
+                        upon::value! { name: "detail" },
+                    ]),
+                },
+            }
+        );
+        assert_eq!(skipped, ["cpr.skipped", "cpr.libraries[1].include_dir"]);
+    }
+
+    #[test]
+    fn restores_skipped_answers_as_empty() {
+        let project = tempfile::tempdir().unwrap();
+        write_answers(project.path(), &answers()).unwrap();
+        let written =
+            std::fs::read_to_string(project.path().join(PROJECT_DIR).join("answers.toml")).unwrap();
+        assert!(
+            written.starts_with("skipped = [\"cpr.skipped\", \"cpr.libraries[1].include_dir\"]\n")
+        );
+        assert_eq!(read_answers(project.path()).unwrap(), answers());
+    }
+
+    #[test]
+    fn restores_skipped_list_entries_in_place() {
+        let mut list = upon::Value::List(vec![upon::Value::from(1), upon::Value::from(3)]);
+        restore_none(&mut list, &["0"]);
+        restore_none(&mut list, &["2"]);
+        assert_eq!(
+            list,
+            upon::Value::List(vec![
+                upon::Value::None,
+                upon::Value::from(1),
+                upon::Value::None,
+                upon::Value::from(3),
+            ])
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use crate::config::{Config, ConfigError, Registry};
+use crate::errors::RegistryError;
+use crate::git::clone_repository;
+use crate::repo::RepoPath;
+use miette::IntoDiagnostic;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// A registry index file listing templates
+#[derive(Debug, Default, Deserialize)]
+struct Index {
+    #[serde(default)]
+    templates: Vec<RegistryEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegistryEntry {
+    /// Short name used in place of a repository path (ex. cpp)
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub languages: Vec<String>,
+    /// Repository path of the template (ex. gh:cpr-rs/cpp)
+    pub source: String,
+    /// Registry the entry was read from
+    #[serde(skip)]
+    pub registry: String,
+}
+
+impl RegistryEntry {
+    fn matches(&self, term: &str) -> bool {
+        let term = term.to_lowercase();
+        self.name.to_lowercase().contains(&term)
+            || self.description.to_lowercase().contains(&term)
+            || self
+                .tags
+                .iter()
+                .chain(&self.languages)
+                .any(|t| t.to_lowercase().contains(&term))
+    }
+}
+
+/// Local clones of registry repositories
+fn cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .or_else(dirs::home_dir)
+        .expect("Could not determine cache directory")
+        .join("cpr")
+        .join("registries")
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Name of the cache directory of a registry repository, keeping ASCII alphanumerics
+/// and writing every other byte as `_XX` so that distinct repositories never share it
+fn cache_name(repo: &str) -> String {
+    repo.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() {
+                (b as char).to_string()
+            } else {
+                format!("_{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+/// Resolve the index file of a registry, cloning registry repositories on first use
+/// or when `update` is set
+fn index_path(registry: &Registry, config: &Config, update: bool) -> miette::Result<PathBuf> {
+    match (&registry.path, &registry.repo) {
+        (Some(path), _) => Ok(expand_home(path)),
+        (None, Some(repo)) => {
+            let directory = cache_dir().join(cache_name(repo));
+            if update && directory.exists() {
+                std::fs::remove_dir_all(&directory).into_diagnostic()?;
+            }
+            if !directory.exists() {
+                std::fs::create_dir_all(&directory).into_diagnostic()?;
+                if let Err(e) = clone_repository(&directory, &RepoPath::parse(repo), config) {
+                    // don't leave a half cloned registry behind
+                    let _ = std::fs::remove_dir_all(&directory);
+                    return Err(e);
+                }
+            }
+            let file = registry.file.as_deref().unwrap_or(Path::new("index.toml"));
+            Ok(directory.join(file))
+        }
+        (None, None) => Err(RegistryError::MissingSource).into_diagnostic(),
+    }
+}
+
+fn read_index(path: &Path) -> miette::Result<Index> {
+    log::debug!("reading registry index from file: {:?}", path);
+    let input = std::fs::read_to_string(path)
+        .map_err(|_| RegistryError::IndexReadFail(path.display().to_string()))
+        .into_diagnostic()?;
+    if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::from_str(&input)
+            .map_err(|e| RegistryError::JsonParseFail(path.display().to_string(), e.to_string()))
+            .into_diagnostic()
+    } else {
+        toml::from_str(&input)
+            .map_err(|e| ConfigError::from_serde_error(input, e).into())
+    }
+}
+
+/// Every template listed by the registries in the config, in registry order
+pub fn entries(config: &Config, update: bool) -> miette::Result<Vec<RegistryEntry>> {
+    let mut entries = Vec::new();
+    for registry in &config.registries {
+        let path = index_path(registry, config, update)?;
+        let name = match &registry.repo {
+            Some(repo) => repo.clone(),
+            None => path.display().to_string(),
+        };
+        for mut entry in read_index(&path)?.templates {
+            entry.registry = name.clone();
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+pub fn search<'a>(entries: &'a [RegistryEntry], term: &str) -> Vec<&'a RegistryEntry> {
+    entries.iter().filter(|e| e.matches(term)).collect()
+}
+
+pub fn find<'a>(entries: &'a [RegistryEntry], name: &str) -> Option<&'a RegistryEntry> {
+    entries.iter().find(|e| e.name == name)
+}
+
+/// Resolve a short registry name (ex. `cpp` or `cpp@v2`) to the repository path of the template
+///
+/// Anything that already looks like a repository path is returned unchanged.
+pub fn resolve(config: &Config, repo_path: &str) -> miette::Result<String> {
+    let path = RepoPath::parse(repo_path);
+    if path.prefix.is_some() || path.repo.contains('/') || config.registries.is_empty() {
+        return Ok(repo_path.to_string());
+    }
+
+    let entries = entries(config, false)?;
+    match find(&entries, &path.repo) {
+        Some(entry) => {
+            let mut source = RepoPath::parse(&entry.source);
+            if path.reference.is_some() {
+                source.reference = path.reference;
+            }
+            log::debug!("resolved `{}` to `{}`", repo_path, source);
+            Ok(source.to_string())
+        }
+        None => Ok(repo_path.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_names_keep_repositories_apart() {
+        assert_eq!(cache_name("org/a-b"), "org_2Fa_2Db");
+        assert_eq!(cache_name("org/a_b"), "org_2Fa_5Fb");
+        assert_ne!(cache_name("org/a_2Db"), cache_name("org/a-b"));
+        assert_eq!(cache_name("gh:org/é"), "gh_3Aorg_2F_C3_A9");
+    }
+}
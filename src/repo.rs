@@ -0,0 +1,167 @@
+use std::fmt;
+
+/// A template repository path in the format `prefix:repo//subdir@ref`
+///
+/// The prefix, subdirectory and ref are optional: `cpr-rs/cpp` uses the default service, the
+/// root and the default branch of the repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoPath {
+    /// Service prefix (ex. gh)
+    pub prefix: Option<String>,
+    /// Repository as understood by the service (ex. cpr-rs/cpp)
+    pub repo: String,
+    /// Directory of the template within the repository (ex. lib-header-only)
+    pub subdir: Option<String>,
+    /// Branch, tag or commit to check out (ex. v2)
+    pub reference: Option<String>,
+}
+
+impl RepoPath {
+    pub fn parse(repo_path: &str) -> Self {
+        let (prefix, rest) = match repo_path.split_once(':') {
+            Some((prefix, rest)) => (Some(prefix.to_string()), rest),
+            None => (None, repo_path),
+        };
+        let (rest, reference) = match rest.rsplit_once('@') {
+            Some((rest, reference)) => (rest, Some(reference.to_string())),
+            None => (rest, None),
+        };
+        let (repo, subdir) = match rest.split_once("//") {
+            Some((repo, subdir)) => (repo.to_string(), Some(subdir.to_string())),
+            None => (rest.to_string(), None),
+        };
+        Self {
+            prefix,
+            repo,
+            subdir,
+            reference,
+        }
+    }
+}
+
+impl RepoPath {
+    /// First segment of the repository (ex. `org` for `org/team/cpp`)
+    pub fn owner(&self) -> &str {
+        self.repo.split('/').next().unwrap_or_default()
+    }
+
+    /// Last segment of the repository (ex. `cpp` for `org/team/cpp`)
+    pub fn name(&self) -> &str {
+        self.repo.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// Every segment but the last, for nested groups (ex. `org/team` for `org/team/cpp`)
+    pub fn group(&self) -> &str {
+        self.repo
+            .rsplit_once('/')
+            .map(|(group, _)| group)
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for RepoPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(prefix) = &self.prefix {
+            write!(f, "{}:", prefix)?;
+        }
+        write!(f, "{}", self.repo)?;
+        if let Some(subdir) = &self.subdir {
+            write!(f, "//{}", subdir)?;
+        }
+        if let Some(reference) = &self.reference {
+            write!(f, "@{}", reference)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo_path(
+        prefix: Option<&str>,
+        repo: &str,
+        subdir: Option<&str>,
+        reference: Option<&str>,
+    ) -> RepoPath {
+        RepoPath {
+            prefix: prefix.map(String::from),
+            repo: repo.to_string(),
+            subdir: subdir.map(String::from),
+            reference: reference.map(String::from),
+        }
+    }
+
+    #[test]
+    fn parses_every_part() {
+        assert_eq!(
+            RepoPath::parse("gl:org/team/cpp//lib-header-only@v2"),
+            repo_path(
+                Some("gl"),
+                "org/team/cpp",
+                Some("lib-header-only"),
+                Some("v2")
+            )
+        );
+    }
+
+    #[test]
+    fn parses_optional_parts_alone() {
+        assert_eq!(
+            RepoPath::parse("cpr-rs/cpp"),
+            repo_path(None, "cpr-rs/cpp", None, None)
+        );
+        assert_eq!(
+            RepoPath::parse("gh:cpr-rs/cpp"),
+            repo_path(Some("gh"), "cpr-rs/cpp", None, None)
+        );
+        assert_eq!(
+            RepoPath::parse("cpr-rs/cpp//lib"),
+            repo_path(None, "cpr-rs/cpp", Some("lib"), None)
+        );
+        assert_eq!(
+            RepoPath::parse("cpr-rs/cpp@main"),
+            repo_path(None, "cpr-rs/cpp", None, Some("main"))
+        );
+    }
+
+    #[test]
+    fn parses_nested_subdirectories() {
+        assert_eq!(
+            RepoPath::parse("gh:cpr-rs/cpp//templates/lib@v1.2.0"),
+            repo_path(
+                Some("gh"),
+                "cpr-rs/cpp",
+                Some("templates/lib"),
+                Some("v1.2.0")
+            )
+        );
+    }
+
+    #[test]
+    fn displays_as_parsed() {
+        for path in [
+            "cpr-rs/cpp",
+            "gh:cpr-rs/cpp",
+            "cpr-rs/cpp//lib",
+            "cpr-rs/cpp@v2",
+            "gl:org/team/cpp//templates/lib@v2",
+        ] {
+            assert_eq!(RepoPath::parse(path).to_string(), path);
+        }
+    }
+
+    #[test]
+    fn splits_the_repository_into_segments() {
+        let path = RepoPath::parse("gl:org/team/cpp");
+        assert_eq!(path.owner(), "org");
+        assert_eq!(path.group(), "org/team");
+        assert_eq!(path.name(), "cpp");
+
+        let path = RepoPath::parse("cpp");
+        assert_eq!(path.owner(), "cpp");
+        assert_eq!(path.group(), "");
+        assert_eq!(path.name(), "cpp");
+    }
+}
@@ -0,0 +1,22 @@
+/// Files of the starter template written by `cpr template new`, by path
+///
+/// Together they use every question type and formatter, and the fixture answers every question.
+pub const FILES: &[(&str, &str)] = &[
+    ("cpr.toml", include_str!("scaffold/cpr.toml")),
+    (".env", include_str!("scaffold/.env")),
+    ("CMakeLists.txt", include_str!("scaffold/CMakeLists.txt")),
+    ("README.md", include_str!("scaffold/README.md")),
+    ("src/main.cpp", include_str!("scaffold/src/main.cpp")),
+    (
+        "_partials/banner.txt",
+        include_str!("scaffold/_partials/banner.txt"),
+    ),
+    (
+        "_generators/header/header.hpp",
+        include_str!("scaffold/_generators/header/header.hpp"),
+    ),
+    (
+        "_tests/default.toml",
+        include_str!("scaffold/_tests/default.toml"),
+    ),
+];
@@ -0,0 +1,8 @@
+// Copyright (c) {{ year }} {{ author }}
+#pragma once
+
+namespace {{ vars.namespace }} {
+
+class {{ gen.name | pascal }} {};
+
+} // namespace {{ vars.namespace }}
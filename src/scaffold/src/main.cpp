@@ -0,0 +1,14 @@
+{% include "banner" %}
+#include <iostream>
+
+namespace {{ vars.namespace }} {
+
+// {{ project.name | upper }}
+constexpr int {{ project.name | constant }}_VERSION = 1;
+constexpr const char* {{ project.name | camel }}Name = "{{ project.name }}";
+
+} // namespace {{ vars.namespace }}
+
+int main() {
+    std::cout << {{ vars.namespace }}::{{ project.name | camel }}Name << '\n';
+}
@@ -0,0 +1,130 @@
+use crate::config::ConfigError;
+use miette::IntoDiagnostic;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Directory of a template holding answer fixtures and the snapshots rendered from them
+pub const TESTS_DIR: &str = "_tests";
+
+/// Answers `cpr template test` renders a template with, read from `_tests/<name>.toml`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Fixture {
+    #[serde(skip)]
+    pub name: String,
+    #[serde(default)]
+    pub project: FixtureProject,
+    /// Answers to the template questions, by key
+    #[serde(default, skip_serializing_if = "toml::Table::is_empty")]
+    pub answers: toml::Table,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FixtureProject {
+    pub name: String,
+    pub author: String,
+    /// Fixed so that snapshots don't change with the current year
+    pub year: Option<i32>,
+}
+
+impl Default for FixtureProject {
+    fn default() -> Self {
+        Self {
+            name: "my_project".to_string(),
+            author: "John Doe".to_string(),
+            year: None,
+        }
+    }
+}
+
+/// Paths of the fixtures of the template at `root`, sorted by name
+pub fn fixture_paths(root: &Path) -> Vec<PathBuf> {
+    let dir = root.join(TESTS_DIR);
+    let mut paths = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "toml"))
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+/// Every fixture of the template at `root`, sorted by name
+pub fn fixtures(root: &Path) -> miette::Result<Vec<Fixture>> {
+    let paths = fixture_paths(root);
+    let mut fixtures = Vec::with_capacity(paths.len());
+    for path in paths {
+        log::debug!("reading fixture from file: {:?}", path);
+        let input = std::fs::read_to_string(&path).into_diagnostic()?;
+        let mut fixture: Fixture =
+            toml::from_str(&input).map_err(|e| ConfigError::from_serde_error(&input, e))?;
+        fixture.name = path.file_stem().unwrap().to_string_lossy().to_string();
+        fixtures.push(fixture);
+    }
+    Ok(fixtures)
+}
+
+/// Directory holding the expected output of a fixture
+pub fn snapshot_dir(root: &Path, name: &str) -> PathBuf {
+    root.join(TESTS_DIR).join(name)
+}
+
+/// Differences between a snapshot and a rendered tree, empty if they match
+pub fn compare(snapshot: &Path, rendered: &Path) -> miette::Result<Vec<String>> {
+    let expected = files(snapshot);
+    let actual = files(rendered);
+    let mut differences = Vec::new();
+
+    for path in expected.union(&actual) {
+        let name = path.to_string_lossy().replace('\\', "/");
+        match (expected.contains(path), actual.contains(path)) {
+            (true, false) => differences.push(format!("missing `{}`", name)),
+            (false, true) => differences.push(format!("unexpected `{}`", name)),
+            _ => {
+                let before = std::fs::read(snapshot.join(path)).into_diagnostic()?;
+                let after = std::fs::read(rendered.join(path)).into_diagnostic()?;
+                if before == after {
+                    continue;
+                }
+                match (String::from_utf8(before), String::from_utf8(after)) {
+                    (Ok(before), Ok(after)) => differences.push(
+                        similar::TextDiff::from_lines(&before, &after)
+                            .unified_diff()
+                            .header(&format!("snapshot/{}", name), &format!("rendered/{}", name))
+                            .to_string(),
+                    ),
+                    _ => differences.push(format!("binary file `{}` differs", name)),
+                }
+            }
+        }
+    }
+    Ok(differences)
+}
+
+/// Replace a snapshot with a rendered tree
+pub fn bless(snapshot: &Path, rendered: &Path) -> miette::Result<()> {
+    if snapshot.exists() {
+        std::fs::remove_dir_all(snapshot).into_diagnostic()?;
+    }
+    for path in files(rendered) {
+        let target = snapshot.join(&path);
+        std::fs::create_dir_all(target.parent().unwrap()).into_diagnostic()?;
+        std::fs::copy(rendered.join(&path), target).into_diagnostic()?;
+    }
+    // a template rendering no files still gets a snapshot
+    std::fs::create_dir_all(snapshot).into_diagnostic()
+}
+
+/// Files of a tree relative to its root
+fn files(root: &Path) -> BTreeSet<PathBuf> {
+    walkdir::WalkDir::new(root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_type().is_dir())
+        .map(|e| e.path().strip_prefix(root).unwrap().to_path_buf())
+        .collect()
+}
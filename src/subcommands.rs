@@ -1,17 +1,24 @@
-use crate::config::Config;
-use crate::errors::{TemplateConfigError, ProjectInitError};
-use crate::format;
+use crate::config::{self, Config, ConfigErrorKind};
+use crate::document;
+use crate::errors::{GeneratorError, ProjectInitError, TemplateConfigError};
+use crate::extract::{self, Replacements};
+use crate::git;
+use crate::license;
+use crate::lint;
+use crate::manifest::{self, Choice, Choices, QuestionKind, TemplateQuestion};
+use crate::migrate::CONFIG_VERSION;
+use crate::project::{self, Generators, LayerRecord, LayerRecords};
+use crate::registry::{self, RegistryEntry};
+use crate::repo::RepoPath;
+use crate::scaffold;
+use crate::snapshot::{self, Fixture, FixtureProject};
+use crate::template::{self, Evaluator, Overwrite, Renderer, Secrets, Template};
+use crate::variables;
 use chrono::Datelike;
-use git2::{
-    build::{CheckoutBuilder, RepoBuilder},
-    FetchOptions, RemoteCallbacks,
-};
-use indicatif::{ProgressBar, ProgressStyle};
 use miette::IntoDiagnostic;
 use requestty::Question;
 use std::{
-    cell::RefCell,
-    collections::HashMap,
+    collections::BTreeMap,
     path::{Path, PathBuf},
 };
 
@@ -21,7 +28,7 @@ pub struct ProjectInfo<'a> {
     config: &'a Config,
 }
 
-pub fn prompt_project_info(config: &Config) -> miette::Result<ProjectInfo> {
+pub fn prompt_project_info(config: &Config) -> miette::Result<ProjectInfo<'_>> {
     let questions = vec![
         Question::input("project_name")
             .message("Project name?")
@@ -43,362 +50,545 @@ pub fn prompt_project_info(config: &Config) -> miette::Result<ProjectInfo> {
     })
 }
 
-fn prompt_template_questions(template_questions: Vec<toml::Value>) -> miette::Result<upon::Value> {
-    let mut questions = Vec::<Question>::with_capacity(template_questions.len());
-    for template_question in template_questions {
-        match template_question {
-            toml::Value::Table(table) => {
-                let key = table.get("key").unwrap().as_str().unwrap();
-                let message = table.get("message").unwrap().as_str().unwrap();
-                let ty = table.get("type").unwrap().as_str().unwrap();
-
-                match ty {
-                    "confirm" => {
-                        questions.push(Question::confirm(key).message(message).build());
-                    }
-                    "input" => {
-                        questions.push(Question::input(key).message(message).build());
-                    }
-                    "int" => {
-                        questions.push(Question::int(key).message(message).build());
-                    }
-                    "float" => {
-                        questions.push(Question::float(key).message(message).build());
-                    }
-                    "select" | "multi_select" | "order_select" => {
-                        let choices = table.get("choices").unwrap();
-                        match choices {
-                            toml::Value::Array(choices) => {
-                                let mut items =
-                                    Vec::<requestty::question::Choice<String>>::with_capacity(
-                                        choices.len(),
-                                    );
-                                let mut items_raw = Vec::<String>::with_capacity(choices.len());
-                                for choice in choices {
-                                    if let toml::Value::String(choice) = choice {
-                                        if choice == "cpr_sep" {
-                                            items.push(
-                                                requestty::question::Choice::DefaultSeparator,
-                                            );
-                                        } else {
-                                            items.push(choice.into());
-                                            items_raw.push(choice.clone());
-                                        }
-                                    }
-                                }
-                                questions.push(match ty {
-                                    "select" => Question::select(key)
-                                        .message(message)
-                                        .choices(items)
-                                        .build(),
-                                    "multi_select" => Question::multi_select(key)
-                                        .message(message)
-                                        .choices(items)
-                                        .build(),
-                                    "order_select" => Question::order_select(key)
-                                        .message(message)
-                                        .choices(items_raw)
-                                        .build(),
-                                    _ => unreachable!(),
-                                });
-                            }
-                            _ => {
-                                eprintln!(
-                                    "! WARN: Expected array of *tables* for [questions.{}.choices] in cpr.toml, skipping",
-                                    key
-                                );
-                            }
-                        }
-                    }
-                    _ => {
-                        eprintln!(
-                            "! WARN: Unknown question type `{}` for key `{}`, skipping",
-                            ty, key
-                        );
-                    }
+/// Prompt for template questions, `answered` holds answers given earlier that `when` conditions
+/// and computed choices may refer to
+///
+/// Questions are asked one at a time, so that each of them sees the answers before it.
+fn prompt_template_questions(
+    template_questions: Vec<TemplateQuestion>,
+    answered: &upon::Value,
+) -> miette::Result<upon::Value> {
+    let evaluator = Evaluator::new();
+    // questions that aren't answered yet are still defined
+    let mut cpr = match answered {
+        upon::Value::Map(answered) => answered.clone(),
+        _ => BTreeMap::new(),
+    };
+    cpr.extend(
+        template_questions
+            .iter()
+            .map(|q| (q.key.clone(), upon::Value::None)),
+    );
+
+    let mut answers = BTreeMap::new();
+    for question in template_questions {
+        let key = question.key.clone();
+        let secret = question.is_secret();
+        let asked = match &question.when {
+            Some(expr) => match evaluator.holds(expr, upon::value! { cpr: cpr.clone() }) {
+                Ok(holds) => holds,
+                Err(e) => {
+                    eprintln!("! WARN: Failed to evaluate `when = \"{}\"`: {:#}", expr, e);
+                    false
                 }
+            },
+            None => true,
+        };
+        let answer = if asked {
+            prompt_question(&evaluator, question, &cpr)?
+        } else {
+            upon::Value::None
+        };
+        if secret {
+            log::debug!("answered `{}` with {}", key, template::MASK);
+        } else {
+            log::debug!("answered `{}` with {:?}", key, answer);
+        }
+        cpr.insert(key.clone(), answer.clone());
+        answers.insert(key, answer);
+    }
+    Ok(upon::Value::Map(answers))
+}
+
+/// Ask a single question, returning the answer to store in `cpr`
+fn prompt_question(
+    evaluator: &Evaluator,
+    question: TemplateQuestion,
+    cpr: &BTreeMap<String, upon::Value>,
+) -> miette::Result<upon::Value> {
+    let TemplateQuestion {
+        key, message, kind, ..
+    } = question;
+    if let QuestionKind::List { questions } = kind {
+        return prompt_entries(&key, &message, questions, cpr);
+    }
+    let listed = match kind.choices() {
+        Some(choices) => list_choices(evaluator, &key, choices, cpr)?,
+        None => Vec::new(),
+    };
+    let options = listed.iter().filter(|c| c.selectable()).collect::<Vec<_>>();
+    if kind.choices().is_some() && options.is_empty() {
+        log::debug!("skipping `{}`, none of its choices can be picked", key);
+        return Ok(upon::Value::None);
+    }
+    let ordered = matches!(kind, QuestionKind::OrderSelect { .. });
+    let mut expand_keys = Vec::new();
+
+    let question = match kind {
+        QuestionKind::Confirm => Question::confirm(&key).message(message).build(),
+        QuestionKind::Input { validate } => {
+            let mut question = Question::input(&key).message(message);
+            if let Some(pattern) = validate {
+                question = question.validate(answer_validator(pattern)?);
             }
-            _ => {
-                eprintln!(
-                    "! WARN: Expected array of *tables* for [questions] in cpr.toml, skipping"
-                );
+            question.build()
+        }
+        QuestionKind::Password { validate } => {
+            let mut question = Question::password(&key).message(message).mask('*');
+            if let Some(pattern) = validate {
+                question = question.validate(answer_validator(pattern)?);
             }
+            question.build()
         }
-    }
+        QuestionKind::Editor { extension } => {
+            let mut question = Question::editor(&key).message(message);
+            if let Some(extension) = extension {
+                question = question.extension(extension);
+            }
+            question.build()
+        }
+        QuestionKind::Int => Question::int(&key).message(message).build(),
+        QuestionKind::Float => Question::float(&key).message(message).build(),
+        QuestionKind::License => Question::select(&key)
+            .message(message)
+            .choices(license::ids().map(String::from))
+            .build(),
+        QuestionKind::Select { .. } => Question::select(&key)
+            .message(message)
+            .choices(listed.iter().map(ListedChoice::item))
+            .build(),
+        QuestionKind::MultiSelect { .. } => Question::multi_select(&key)
+            .message(message)
+            .choices(listed.iter().map(ListedChoice::item))
+            .build(),
+        QuestionKind::RawSelect { .. } => Question::raw_select(&key)
+            .message(message)
+            .choices(listed.iter().map(ListedChoice::item))
+            .build(),
+        // order_select has no separators, every choice is ordered
+        QuestionKind::OrderSelect { .. } => Question::order_select(&key)
+            .message(message)
+            .choices(options.iter().map(|c| c.text()))
+            .build(),
+        QuestionKind::Expand { .. } => {
+            let labels = options.iter().map(|c| c.label.as_str()).collect::<Vec<_>>();
+            expand_keys = manifest::expand_keys(&labels)
+                .map_err(|label| TemplateConfigError::NoShortcutKey(label.to_string()))
+                .into_diagnostic()?;
+            let mut keys = expand_keys.iter();
+            let mut question = Question::expand(&key).message(message);
+            for choice in &listed {
+                question = match choice.item() {
+                    requestty::question::Choice::Choice(text) => {
+                        question.choice(*keys.next().unwrap(), text)
+                    }
+                    requestty::question::Choice::Separator(text) => question.separator(text),
+                    requestty::question::Choice::DefaultSeparator => question.default_separator(),
+                };
+            }
+            question.build()
+        }
+        QuestionKind::List { .. } => unreachable!("lists are prompted entry by entry"),
+    };
 
-    let mut map = HashMap::<String, upon::Value>::new();
-    for (key, answer) in requestty::prompt(questions).into_diagnostic()? {
-        use requestty::{Answer, ExpandItem, ListItem};
-        map.insert(
-            key,
-            match answer {
-                Answer::String(str) => upon::Value::String(str),
-                Answer::ListItem(ListItem { text, .. }) => upon::Value::String(text),
-                Answer::ExpandItem(ExpandItem { text, .. }) => upon::Value::String(text),
-                Answer::Int(num) => upon::Value::Integer(num),
-                Answer::Float(num) => upon::Value::Float(num),
-                Answer::Bool(bool) => upon::Value::Bool(bool),
-                Answer::ListItems(mut items) => {
-                    items.sort_by(|a, b| a.index.cmp(&b.index));
-                    upon::Value::List(
-                        items
-                            .into_iter()
-                            .map(|item| upon::Value::String(item.text))
-                            .collect(),
-                    )
-                }
-            },
-        );
+    // picked choices are stored as their value
+    Ok(match requestty::prompt_one(question).into_diagnostic()? {
+        requestty::Answer::ListItem(item) if !listed.is_empty() => listed[item.index].value(),
+        requestty::Answer::ListItems(items) if ordered => {
+            let values = items.iter().map(|item| options[item.index].value());
+            upon::Value::List(values.collect())
+        }
+        requestty::Answer::ListItems(mut items) => {
+            items.sort_by_key(|item| item.index);
+            let values = items.iter().map(|item| listed[item.index].value());
+            upon::Value::List(values.collect())
+        }
+        requestty::Answer::ExpandItem(item) => {
+            let index = expand_keys.iter().position(|k| *k == item.key).unwrap();
+            options[index].value()
+        }
+        answer => answer_value(answer),
+    })
+}
+
+/// Prompt for entries of a `list` question until the user declines to add another
+///
+/// The nested questions see the answers given before the list, along with the earlier answers
+/// of the same entry.
+fn prompt_entries(
+    key: &str,
+    message: &str,
+    questions: Vec<TemplateQuestion>,
+    cpr: &BTreeMap<String, upon::Value>,
+) -> miette::Result<upon::Value> {
+    let mut entries = Vec::new();
+    loop {
+        let more = Question::confirm(key)
+            .message(message)
+            .default(false)
+            .build();
+        let more = requestty::prompt_one(more).into_diagnostic()?;
+        if !more.as_bool().unwrap_or_default() {
+            break;
+        }
+        let answered = upon::Value::Map(cpr.clone());
+        entries.push(prompt_template_questions(questions.clone(), &answered)?);
     }
+    Ok(upon::Value::List(entries))
+}
 
-    upon::to_value(map).into_diagnostic()
+/// Validation of a text answer against the `validate` regular expression of its question
+fn answer_validator(
+    pattern: String,
+) -> miette::Result<impl FnMut(&str, &requestty::Answers) -> Result<(), String>> {
+    let regex = regex::Regex::new(&pattern).into_diagnostic()?;
+    Ok(move |answer: &str, _: &requestty::Answers| {
+        if regex.is_match(answer) {
+            Ok(())
+        } else {
+            Err(format!("Answer must match `{}`", pattern))
+        }
+    })
 }
 
-struct GitCloneState {
-    total: usize,
-    current: usize,
-    path: Option<PathBuf>,
-    started_resolution: bool,
+/// A choice as shown to the user, along with the answer stored when it is picked
+struct ListedChoice {
+    /// Label of the choice, or the text of a separator
+    label: String,
+    description: Option<String>,
+    /// `None` for separators
+    value: Option<upon::Value>,
+    disabled: bool,
 }
 
-fn clone_repository(directory: &Path, repo_path: String, config: &Config) -> miette::Result<()> {
-    let clone_state = RefCell::new(GitCloneState {
-        total: 0,
-        current: 0,
-        path: None,
-        started_resolution: false,
-    });
-    let bar = ProgressBar::new(100);
-    bar.set_style(
-        ProgressStyle::with_template(
-            "{spinner} [{elapsed_precise}] [{bar:30}] {pos:>7}/{len:7} {msg}",
-        )
-        .unwrap()
-        .progress_chars("#>-"),
-    );
+impl ListedChoice {
+    fn separator(text: &str) -> Self {
+        Self {
+            label: text.to_string(),
+            description: None,
+            value: None,
+            disabled: false,
+        }
+    }
 
-    let mut cb = RemoteCallbacks::new();
-    cb.transfer_progress(|stats| {
-        let mut state = clone_state.borrow_mut();
-        state.total = stats.total_objects();
-        state.current = stats.received_objects();
-        if state.current == state.total {
-            // resolving deltas
-            bar.set_message(format!(
-                "Resolving deltas {}/{}",
-                stats.indexed_deltas(),
-                stats.total_deltas()
-            ));
-            if state.started_resolution {
-                bar.reset();
+    fn option(label: &str, value: upon::Value, description: Option<&str>, disabled: bool) -> Self {
+        Self {
+            label: label.to_string(),
+            description: description.map(String::from),
+            value: Some(value),
+            disabled,
+        }
+    }
+
+    /// Choice listed in `cpr.toml`
+    fn from_choice(choice: &Choice) -> miette::Result<Self> {
+        Ok(match choice {
+            Choice::Plain(text) => Self::from_text(text.clone()),
+            Choice::Separator(separator) => Self::separator(&separator.separator),
+            Choice::Table(table) => Self::option(
+                &table.label,
+                match &table.value {
+                    Some(value) => upon::to_value(value).into_diagnostic()?,
+                    None => upon::Value::String(table.label.clone()),
+                },
+                table.description.as_deref(),
+                table.disabled,
+            ),
+        })
+    }
+
+    /// Choice computed by an expression, shaped like a listed one
+    fn from_value(value: upon::Value) -> Result<Self, String> {
+        let mut table = match value {
+            upon::Value::String(text) => return Ok(Self::from_text(text)),
+            upon::Value::Map(table) => table,
+            upon::Value::None | upon::Value::List(_) => {
+                return Err("expected strings or tables".to_string());
             }
-            bar.set_length(stats.total_deltas() as u64);
-            bar.set_position(stats.indexed_deltas() as u64);
-            state.started_resolution = true;
-        } else {
-            bar.set_length(state.total as u64);
-            bar.set_position(state.current as u64);
-            bar.set_message(format!(
-                "Cloning {} ({}/{} objects)",
-                repo_path, state.current, state.total
-            ));
-            bar.tick();
+            // numbers and booleans are stored as they are
+            upon::Value::Bool(b) => return Ok(Self::option(&b.to_string(), value, None, false)),
+            upon::Value::Integer(i) => return Ok(Self::option(&i.to_string(), value, None, false)),
+            upon::Value::Float(f) => return Ok(Self::option(&f.to_string(), value, None, false)),
+        };
+        if let Some(upon::Value::String(separator)) = table.get("separator") {
+            return Ok(Self::separator(separator));
         }
-        true
-    });
+        let Some(upon::Value::String(label)) = table.remove("label") else {
+            return Err("choice tables need a `label` string".to_string());
+        };
+        let value = table
+            .remove("value")
+            .unwrap_or_else(|| upon::Value::String(label.clone()));
+        let description = match table.get("description") {
+            Some(upon::Value::String(description)) => Some(description.as_str()),
+            _ => None,
+        };
+        let disabled = matches!(table.get("disabled"), Some(upon::Value::Bool(true)));
+        Ok(Self::option(&label, value, description, disabled))
+    }
 
-    let mut co = CheckoutBuilder::new();
-    co.progress(|path, cur, total| {
-        let mut state = clone_state.borrow_mut();
-        state.path = path.map(|p| p.to_path_buf());
-        state.current = cur;
-        state.total = total;
-        if cur < total {
-            bar.set_length(total as u64);
-            bar.set_position(cur as u64);
-            bar.set_message(format!(
-                "Cloning {} ({}/{} objects)",
-                repo_path, state.current, state.total
-            ));
-            bar.tick();
+    /// Plain choice, `cpr_sep` being a separator
+    fn from_text(text: String) -> Self {
+        if text == manifest::SEPARATOR {
+            Self::separator("")
+        } else {
+            Self::option(&text, upon::Value::String(text.clone()), None, false)
         }
-    });
+    }
 
-    // repo_path is in the format: `prefix:repo`
-    // if prefix is not provided, use the default prefix
-    log::debug!("repo_path: {}", repo_path);
-    let url = if repo_path.contains(':') {
-        let parts: Vec<&str> = repo_path.split(':').collect();
-        let prefix = parts[0];
-        let repo = parts[1];
-        config.clone_url(prefix, repo)
-    } else {
-        config.clone_url(&config.default_service, &repo_path)
-    };
+    fn selectable(&self) -> bool {
+        self.value.is_some() && !self.disabled
+    }
 
-    log::debug!("cloning repository: {}", url);
-
-    let mut fo = FetchOptions::new();
-    fo.remote_callbacks(cb);
-    RepoBuilder::new()
-        .fetch_options(fo)
-        .with_checkout(co)
-        .clone(&url, directory)
-        .map_err(|e| {
-            if e.code() == git2::ErrorCode::NotFound {
-                ProjectInitError::GitRepoNotFound
-            } else {
-                ProjectInitError::GitCloneFail
-            }
-        })
-        .into_diagnostic()?;
+    fn value(&self) -> upon::Value {
+        self.value.clone().unwrap_or_default()
+    }
 
-    // let users decide their own vcs configuration
-    std::fs::remove_dir_all(directory.join(".git"))
-        .map_err(|_| ProjectInitError::GitCloneFail)
-        .into_diagnostic()?;
+    /// Label followed by the description
+    fn text(&self) -> String {
+        match &self.description {
+            Some(description) => format!("{} - {}", self.label, description),
+            None => self.label.clone(),
+        }
+    }
 
-    Ok(())
+    /// Disabled choices are shown as separators, which can't be picked
+    fn item(&self) -> requestty::question::Choice<String> {
+        use requestty::question::Choice;
+        match (&self.value, self.disabled) {
+            (None, _) if self.label.is_empty() => Choice::DefaultSeparator,
+            (None, _) => Choice::Separator(self.label.clone()),
+            (Some(_), true) => Choice::Separator(format!("{} (disabled)", self.text())),
+            (Some(_), false) => Choice::Choice(self.text()),
+        }
+    }
 }
 
-pub fn init(directory: PathBuf, repo_path: String, info: ProjectInfo) -> miette::Result<()> {
-    clone_repository(&directory, repo_path, info.config)?;
-
-    // if `cpr.toml` exists, use it
-    let cpr_path = directory.join("cpr.toml");
-    let mut template_answers = upon::Value::None;
-    if cpr_path.exists() {
-        let cpr = std::fs::read_to_string(&cpr_path)
-            .map_err(|_| TemplateConfigError::FileReadFail)
-            .into_diagnostic()?;
-        let cpr: toml::Value = toml::de::from_str(&cpr)
-            .map_err(|_| TemplateConfigError::TomlParseFail)
-            .into_diagnostic()?;
+/// Choices of a question, computed ones being evaluated against the answers before it
+fn list_choices(
+    evaluator: &Evaluator,
+    key: &str,
+    choices: &Choices,
+    cpr: &BTreeMap<String, upon::Value>,
+) -> miette::Result<Vec<ListedChoice>> {
+    let invalid = |reason: String| TemplateConfigError::InvalidChoices(key.to_string(), reason);
+    match choices {
+        Choices::Listed(choices) => choices.iter().map(ListedChoice::from_choice).collect(),
+        Choices::Computed(expr) => {
+            let value = evaluator
+                .evaluate(expr, upon::value! { cpr: cpr.clone() })
+                .map_err(|e| invalid(format!("{:#}", e)))
+                .into_diagnostic()?;
+            let upon::Value::List(items) = value else {
+                return Err(invalid(format!("`{}` is not a list", expr))).into_diagnostic();
+            };
+            items
+                .into_iter()
+                .map(|item| ListedChoice::from_value(item).map_err(invalid))
+                .collect::<Result<_, _>>()
+                .into_diagnostic()
+        }
+    }
+}
 
-        template_answers = match cpr {
-            toml::Value::Table(table) => {
-                if let Some(qs) = table.get("questions") {
-                    match qs {
-                        toml::Value::Array(questions) => {
-                            prompt_template_questions(questions.clone())?
-                        }
-                        _ => {
-                            eprintln!("! WARN: Expected *array* of tables for [questions] in cpr.toml, skipping");
-                            upon::Value::None
-                        }
-                    }
-                } else {
-                    eprintln!("! WARN: No questions found in cpr.toml");
-                    upon::Value::None
-                }
-            }
-            _ => upon::Value::None,
-        };
+fn answer_value(answer: requestty::Answer) -> upon::Value {
+    use requestty::{Answer, ExpandItem, ListItem};
+    match answer {
+        Answer::String(str) => upon::Value::String(str),
+        Answer::ListItem(ListItem { text, .. }) => upon::Value::String(text),
+        Answer::ExpandItem(ExpandItem { text, .. }) => upon::Value::String(text),
+        Answer::Int(num) => upon::Value::Integer(num),
+        Answer::Float(num) => upon::Value::Float(num),
+        Answer::Bool(bool) => upon::Value::Bool(bool),
+        Answer::ListItems(mut items) => {
+            items.sort_by_key(|item| item.index);
+            upon::Value::List(
+                items
+                    .into_iter()
+                    .map(|item| upon::Value::String(item.text))
+                    .collect(),
+            )
+        }
     }
+}
+
+/// Prompt for the questions of every template and render them into `directory`
+fn render_templates(
+    templates: &[Template],
+    directory: &Path,
+    info: &ProjectInfo,
+    overwrite: Overwrite,
+) -> miette::Result<Renderer> {
+    // questions of every layer are asked once and the answers shared
+    let questions = template::merge_questions(templates);
+    let template_answers = if questions.is_empty() {
+        upon::Value::None
+    } else {
+        prompt_template_questions(questions, &upon::Value::None)?
+    };
 
     let year = chrono::offset::Local::now().year();
 
-    // walk the directory and run template engine
-    let mut engine = upon::Engine::new();
-    let walker = walkdir::WalkDir::new(&directory).sort_by_file_name();
+    // includes defaults for: {{ project.name }} {{ year }} {{ author }}
+    let context = upon::value! {
+        project: {
+            name: &info.project_name,
+        },
+        year: year,
+        author: &info.author,
+        cpr: &template_answers,
+    };
+    render_context(templates, directory, context, overwrite)
+}
 
-    engine.add_formatter("lower", format::lower);
-    engine.add_formatter("upper", format::upper);
-    engine.add_formatter("snake", format::snake);
-    engine.add_formatter("kebab", format::kebab);
-    engine.add_formatter("pascal", format::pascal);
-    engine.add_formatter("camel", format::camel);
-    engine.add_formatter("title", format::title);
+/// Render every template into `directory` with the answers held by `context`
+fn render_context(
+    templates: &[Template],
+    directory: &Path,
+    context: upon::Value,
+    overwrite: Overwrite,
+) -> miette::Result<Renderer> {
+    let questions = template::merge_questions(templates);
+    let license_keys = questions
+        .iter()
+        .filter(|q| matches!(q.kind, QuestionKind::License))
+        .map(|q| q.key.clone())
+        .collect::<Vec<_>>();
+    let variables = variables::merge(templates);
+    let secrets = Secrets::new(&questions, &context).with_variables(&variables);
+    let template_answers = match &context {
+        upon::Value::Map(context) => context.get("cpr").cloned().unwrap_or_default(),
+        _ => upon::Value::None,
+    };
+    // variables are evaluated once every answer is known
+    let vars = variables::evaluate(&variables, &context, &secrets)?;
+    let context = match context {
+        upon::Value::Map(mut context) => {
+            context.insert(variables::ROOT.to_string(), vars);
+            upon::Value::Map(context)
+        }
+        context => context,
+    };
 
-    // skip read errors if enabled
-    let mut skip_all = false;
+    let mut renderer = Renderer::new(context)
+        .with_overwrite(overwrite)
+        .with_secrets(secrets);
 
-    for entry in walker.into_iter().filter_map(|e| e.ok()) {
-        if entry.file_type().is_dir() {
-            continue;
-        }
+    for template in templates {
+        renderer.add_partials(template)?;
+    }
+    // later templates overlay the files of the templates they build on
+    for template in templates {
+        renderer.render_into(template, directory)?;
+    }
 
-        let path = entry.into_path();
-
-        let contents = std::fs::read_to_string(&path).map_err(|_| {
-            ProjectInitError::ReadFileFail(path.file_name().unwrap().to_str().unwrap().to_string())
-        });
-        // instead of returning an error, we can prompt the user to skip the file
-        // the options should be:
-        // 1. Skip this error
-        // 2. Skip all errors
-        // 3. Abort
-
-        if let Err(e) = contents {
-            if skip_all {
-                log::warn!("Skipping file `{}`", path.display());
-                continue;
-            }
+    // write LICENSE for every answered `license` question
+    for key in license_keys {
+        let id = match &template_answers {
+            upon::Value::Map(answers) => match answers.get(&key) {
+                Some(upon::Value::String(id)) => id.clone(),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        let text = license::text(&id)
+            .ok_or_else(|| {
+                let ids = license::ids().collect::<Vec<_>>().join(", ");
+                TemplateConfigError::UnknownLicense(id.clone(), ids)
+            })
+            .into_diagnostic()?;
+        let result = renderer.render_str(text)?;
+        renderer.write(&directory.join("LICENSE"), result)?;
+    }
 
-            log::warn!("Failed to read file `{}`", path.display());
+    Ok(renderer)
+}
 
-            let opt = Question::select("err_policy")
-                .message(format!(
-                    "Failed to read file `{}`: What would you like to do?",
-                    path.file_name().unwrap().to_str().unwrap(),
-                ))
-                .choices(vec![
-                    "Skip this error".to_string(),
-                    "Skip all future errors".into(),
-                    "Abort".into(),
-                ])
-                .build();
-            match requestty::prompt(vec![opt]).into_diagnostic()?["err_policy"]
-                .as_list_item()
-                .unwrap()
-                .text
-                .as_str()
+/// Copy the generators of every template into `.cpr/` along with the answers used, so that
+/// `cpr gen` can render them later
+///
+/// Secrets are left out of the answers, `.cpr/` is usually committed.
+fn install_generators(
+    templates: &[Template],
+    directory: &Path,
+    renderer: &Renderer,
+) -> miette::Result<()> {
+    let mut generators = Generators::default();
+    for template in templates {
+        for (name, generator) in &template.manifest.generators {
+            let source = template.root.join("_generators").join(name);
+            let target = Generators::files_dir(directory, name);
+            for entry in walkdir::WalkDir::new(&source)
+                .into_iter()
+                .filter_map(|e| e.ok())
             {
-                "Skip this error" => continue,
-                "Skip all future errors" => {
-                    skip_all = true;
-                    continue;
+                let path = target.join(entry.path().strip_prefix(&source).unwrap());
+                if entry.file_type().is_dir() {
+                    std::fs::create_dir_all(&path)
+                        .map_err(|_| ProjectInitError::ProjectDirCreateFail)
+                        .into_diagnostic()?;
+                } else {
+                    std::fs::copy(entry.path(), &path)
+                        .map_err(|_| {
+                            ProjectInitError::WriteFileFail(
+                                path.file_name().unwrap().to_str().unwrap().to_string(),
+                            )
+                        })
+                        .into_diagnostic()?;
                 }
-                "Abort" => return Err(e).into_diagnostic(),
-                _ => unreachable!(),
             }
+            generators
+                .generators
+                .insert(name.clone(), generator.clone());
         }
+    }
 
-        let contents = contents.unwrap();
+    if generators.generators.is_empty() {
+        return Ok(());
+    }
+    generators.write(directory)?;
+    let context = renderer.secrets().strip(renderer.context());
+    project::write_answers(directory, &context)
+}
 
-        engine.add_template("tmp", contents).into_diagnostic()?;
+pub fn init(directory: PathBuf, repo_path: String, info: ProjectInfo) -> miette::Result<()> {
+    if directory.read_dir().is_ok_and(|mut d| d.next().is_some()) {
+        return Err(ProjectInitError::ProjectDirNotEmpty).into_diagnostic();
+    }
 
-        let result = engine
-            .template("tmp")
-            // includes defaults for: {{ project.name }} {{ year }} {{ author }}
-            .render(upon::value! {
-                project: {
-                    name: &info.project_name,
-                },
-                year: year,
-                author: &info.author,
-                cpr: &template_answers,
-            })
-            .to_string()
-            .into_diagnostic()?;
+    let staging = tempfile::tempdir().into_diagnostic()?;
+    let templates = template::fetch(&repo_path, info.config, staging.path())?;
+    let renderer = render_templates(&templates, &directory, &info, Overwrite::Always)?;
+    install_generators(&templates, &directory, &renderer)?;
 
-        std::fs::write(&path, result)
-            .map_err(|_| {
-                ProjectInitError::WriteFileFail(
-                    path.file_name().unwrap().to_str().unwrap().to_string(),
-                )
-            })
-            .into_diagnostic()?;
+    println!("Project initialized successfully");
 
-        engine.remove_template("tmp");
-    }
+    Ok(())
+}
 
-    // remove cpr.toml
-    if cpr_path.exists() && std::fs::remove_file(cpr_path).is_err() {
-        eprintln!("! WARN: Failed to remove cpr.toml");
-    }
+pub fn add(directory: PathBuf, repo_path: String, info: ProjectInfo) -> miette::Result<()> {
+    let project = Path::new(".");
 
-    println!("Project initialized successfully");
+    let staging = tempfile::tempdir().into_diagnostic()?;
+    let templates = template::fetch(&repo_path, info.config, staging.path())?;
+    let renderer = render_templates(&templates, &directory, &info, Overwrite::Ask)?;
+
+    // keep track of what the layer added to the project
+    let relative = |path: &PathBuf| path.strip_prefix(project).unwrap_or(path).to_path_buf();
+    let mut records = LayerRecords::from_project(project)?;
+    records.layers.push(LayerRecord {
+        repo: repo_path,
+        added: chrono::offset::Local::now().to_rfc3339(),
+        files: renderer.written.iter().map(relative).collect(),
+        skipped: renderer.skipped.iter().map(relative).collect(),
+    });
+    records.write(project)?;
+
+    println!(
+        "Layer added successfully ({} files written, {} skipped)",
+        renderer.written.len(),
+        renderer.skipped.len()
+    );
 
     Ok(())
 }
@@ -419,3 +609,555 @@ pub fn new(repo_path: String, info: ProjectInfo) -> miette::Result<()> {
 
     Ok(())
 }
+
+pub fn gen(project: &Path, name: Option<String>, args: Vec<String>) -> miette::Result<()> {
+    let generators = Generators::from_project(project)?.generators;
+    if generators.is_empty() {
+        return Err(GeneratorError::NoGenerators).into_diagnostic();
+    }
+
+    let Some(name) = name else {
+        for (name, generator) in &generators {
+            match &generator.description {
+                Some(description) => println!("`{}`: {}", name, description),
+                None => println!("`{}`", name),
+            }
+        }
+        return Ok(());
+    };
+    let generator = generators
+        .get(&name)
+        .ok_or_else(|| GeneratorError::NotFound(name.clone()))
+        .into_diagnostic()?;
+
+    let mut context = match project::read_answers(project)? {
+        upon::Value::Map(answers) => answers,
+        _ => BTreeMap::new(),
+    };
+
+    // generator questions extend the answers the project was created with
+    if !generator.questions.is_empty() {
+        let answered = context.get("cpr").cloned().unwrap_or_default();
+        if let upon::Value::Map(answers) =
+            prompt_template_questions(generator.questions.clone(), &answered)?
+        {
+            match context
+                .entry("cpr".to_string())
+                .or_insert_with(|| upon::Value::Map(BTreeMap::new()))
+            {
+                upon::Value::Map(cpr) => cpr.extend(answers),
+                cpr => *cpr = upon::Value::Map(answers),
+            }
+        }
+    }
+
+    // `net::Socket` -> name: Socket, namespaces: [net]
+    let mut namespaces = args
+        .first()
+        .map(|arg| arg.split("::").map(String::from).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let gen_name = namespaces.pop();
+    // `.` keeps `include/{{ gen.dir }}/...` paths whole without a namespace
+    let dir = if namespaces.is_empty() {
+        ".".to_string()
+    } else {
+        namespaces.join("/")
+    };
+    context.insert(
+        "gen".to_string(),
+        upon::value! {
+            name: gen_name,
+            namespace: namespaces.join("::"),
+            dir: dir,
+            namespaces: namespaces,
+            args: &args,
+        },
+    );
+    context.insert(
+        "year".to_string(),
+        upon::Value::Integer(chrono::offset::Local::now().year().into()),
+    );
+
+    let context = upon::Value::Map(context);
+    let secrets = Secrets::new(&generator.questions, &context);
+    let mut renderer = Renderer::new(context)
+        .with_overwrite(Overwrite::Ask)
+        .with_secrets(secrets);
+    let files_dir = Generators::files_dir(project, &name);
+    for file in &generator.files {
+        let contents = std::fs::read_to_string(files_dir.join(&file.source))
+            .map_err(|_| ProjectInitError::ReadFileFail(file.source.clone()))
+            .into_diagnostic()?;
+        // rendered like the paths of template files, which answers can't move out of the project
+        let Some(path) = renderer.render_path(Path::new(&file.path))? else {
+            log::debug!("leaving out `{}`", file.source);
+            continue;
+        };
+        let result = renderer.render_str(&contents)?;
+        renderer.write(&project.join(path), result)?;
+    }
+
+    for path in &renderer.written {
+        let path = path.strip_prefix(project).unwrap_or(path);
+        println!("Created `{}`", path.display());
+    }
+
+    Ok(())
+}
+
+fn print_entry(entry: &RegistryEntry) {
+    if entry.description.is_empty() {
+        println!("`{}` -> {}", entry.name, entry.source);
+    } else {
+        println!("`{}`: {} -> {}", entry.name, entry.description, entry.source);
+    }
+}
+
+pub fn list(config: &Config, update: bool) -> miette::Result<()> {
+    registry::entries(config, update)?
+        .iter()
+        .for_each(print_entry);
+    Ok(())
+}
+
+pub fn search(config: &Config, term: &str) -> miette::Result<()> {
+    let entries = registry::entries(config, false)?;
+    let found = registry::search(&entries, term);
+    if found.is_empty() {
+        println!("No templates matching `{}`", term);
+    }
+    found.into_iter().for_each(print_entry);
+    Ok(())
+}
+
+/// Show the registry entry of a template if it has one, then the `[template]` section of its
+/// `cpr.toml`
+pub fn info(config: &Config, repo_path: &str) -> miette::Result<()> {
+    let entries = registry::entries(config, false)?;
+    let path = RepoPath::parse(repo_path);
+    let entry = match path.prefix {
+        Some(_) => None,
+        None => registry::find(&entries, &path.repo),
+    };
+    if let Some(entry) = entry {
+        println!("name: {}", entry.name);
+        if !entry.description.is_empty() {
+            println!("description: {}", entry.description);
+        }
+        println!("source: {}", entry.source);
+        if !entry.tags.is_empty() {
+            println!("tags: {}", entry.tags.join(", "));
+        }
+        if !entry.languages.is_empty() {
+            println!("languages: {}", entry.languages.join(", "));
+        }
+        println!("registry: {}", entry.registry);
+        println!();
+    }
+
+    let resolved = registry::resolve(config, &config.resolve_alias(repo_path))?;
+    let staging = tempfile::tempdir().into_diagnostic()?;
+    let template = template::checkout(&resolved, config, staging.path())?;
+    let info = &template.manifest.template;
+    println!("template: {}", info.name.as_deref().unwrap_or(&resolved));
+    if let Some(description) = &info.description {
+        println!("description: {}", description);
+    }
+    if let Some(version) = &info.version {
+        println!("version: {}", version);
+    }
+    if !info.authors.is_empty() {
+        println!("authors: {}", info.authors.join(", "));
+    }
+    if let Some(homepage) = &info.homepage {
+        println!("homepage: {}", homepage);
+    }
+    if !info.tags.is_empty() {
+        println!("tags: {}", info.tags.join(", "));
+    }
+    if let Some(range) = info.cpr_versions() {
+        let current = env!("CARGO_PKG_VERSION");
+        let compatibility = if info.supports(&semver::Version::parse(current).unwrap()) {
+            "compatible"
+        } else {
+            "incompatible"
+        };
+        println!(
+            "cpr version: {} ({} with {})",
+            range, compatibility, current
+        );
+    }
+    let bases = template.bases();
+    if !bases.is_empty() {
+        println!("based on: {}", bases.join(", "));
+    }
+    if !template.manifest.questions.is_empty() {
+        let keys = template.manifest.questions.iter().map(|q| q.key.as_str());
+        println!("questions: {}", keys.collect::<Vec<_>>().join(", "));
+    }
+    Ok(())
+}
+
+/// Open the user configuration in the editor until it is valid, or restore it
+pub fn edit_config(path: &Path) -> miette::Result<()> {
+    let original = std::fs::read_to_string(path).into_diagnostic()?;
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    loop {
+        // editors are often given with arguments (ex. `code --wait`)
+        let mut args = editor.split_whitespace();
+        let status = std::process::Command::new(args.next().unwrap_or("vi"))
+            .args(args)
+            .arg(path)
+            .status();
+        if !status.is_ok_and(|s| s.success()) {
+            return Err(ConfigErrorKind::EditorFail(editor)).into_diagnostic();
+        }
+
+        let report = match config::validate_file(path) {
+            Ok(_) => return Ok(()),
+            Err(report) => report,
+        };
+        eprintln!("{:?}", report);
+        let again = Question::confirm("again")
+            .message("Edit the configuration again?")
+            .default(true)
+            .build();
+        let again = requestty::prompt_one(again).into_diagnostic()?;
+        if !again.as_bool().unwrap_or(false) {
+            document::write_atomic(path, &original)?;
+            println!("Restored the previous configuration");
+            return Ok(());
+        }
+    }
+}
+
+/// Upgrade the user configuration to the current format, showing what changed
+pub fn migrate_config(path: &Path, dry_run: bool) -> miette::Result<()> {
+    let before = std::fs::read_to_string(path).into_diagnostic()?;
+    let (doc, changes) = document::read(path)?;
+    if changes.is_empty() {
+        println!("Configuration is up to date (version {})", CONFIG_VERSION);
+        return Ok(());
+    }
+
+    for change in &changes {
+        println!("- {}", change);
+    }
+    let after = doc.to_string();
+    let name = path.display().to_string();
+    print!(
+        "{}",
+        similar::TextDiff::from_lines(&before, &after)
+            .unified_diff()
+            .header(&name, &name)
+    );
+    if !dry_run {
+        document::write(path, &doc)?;
+        println!("Migrated {} to version {}", name, CONFIG_VERSION);
+    }
+    Ok(())
+}
+
+/// Report every problem `cpr template lint` finds in the template at `path`
+pub fn lint_template(path: &Path) -> miette::Result<()> {
+    let problems = lint::lint(path)?;
+    let errors = problems.iter().filter(|p| p.is_error()).count();
+    let warnings = problems.len() - errors;
+    for problem in problems {
+        eprintln!("{:?}", miette::Report::new(problem));
+    }
+
+    if errors > 0 {
+        return Err(TemplateConfigError::LintFail(errors)).into_diagnostic();
+    }
+    match warnings {
+        0 => println!("No problems found"),
+        _ => println!("No errors found, {} warning(s)", warnings),
+    }
+    Ok(())
+}
+
+/// Render the template at `path` with every fixture in `_tests/` and compare the output with
+/// the snapshots next to them, or replace the snapshots if `bless` is set
+pub fn test_template(config: &Config, path: &Path, bless: bool) -> miette::Result<()> {
+    let fixtures = snapshot::fixtures(path)?;
+    if fixtures.is_empty() {
+        return Err(TemplateConfigError::NoFixtures).into_diagnostic();
+    }
+
+    let staging = tempfile::tempdir().into_diagnostic()?;
+    let template = Template::load(path.to_path_buf())?;
+    let templates = template::with_bases(template, config, staging.path())?;
+    let questions = template::merge_questions(&templates);
+
+    let mut failed = 0;
+    for fixture in &fixtures {
+        let rendered = tempfile::tempdir().into_diagnostic()?;
+        let context = fixture_context(fixture, &questions)?;
+        let renderer = render_context(&templates, rendered.path(), context, Overwrite::Always)?;
+        install_generators(&templates, rendered.path(), &renderer)?;
+
+        let snapshot = snapshot::snapshot_dir(path, &fixture.name);
+        let differences = if snapshot.is_dir() {
+            snapshot::compare(&snapshot, rendered.path())?
+        } else {
+            vec![format!(
+                "no snapshot in `{}/{}`, run with --bless to create it",
+                snapshot::TESTS_DIR,
+                fixture.name
+            )]
+        };
+        if differences.is_empty() {
+            println!("test `{}` ... ok", fixture.name);
+        } else if bless {
+            snapshot::bless(&snapshot, rendered.path())?;
+            println!("test `{}` ... blessed", fixture.name);
+        } else {
+            failed += 1;
+            println!("test `{}` ... FAILED", fixture.name);
+            for difference in differences {
+                println!("{}", difference.trim_end());
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", fixtures.len() - failed, failed);
+    if failed > 0 {
+        return Err(TemplateConfigError::TestFail(failed)).into_diagnostic();
+    }
+    Ok(())
+}
+
+/// Context a fixture renders the template with, as `cpr init` would build it
+fn fixture_context(
+    fixture: &Fixture,
+    questions: &[TemplateQuestion],
+) -> miette::Result<upon::Value> {
+    let answers = fixture_answers(fixture, questions, &fixture.answers, "")?;
+
+    let year = fixture
+        .project
+        .year
+        .unwrap_or_else(|| chrono::offset::Local::now().year());
+    let template_answers = if answers.is_empty() {
+        upon::Value::None
+    } else {
+        upon::Value::Map(answers)
+    };
+    Ok(upon::value! {
+        project: {
+            name: &fixture.project.name,
+        },
+        year: year,
+        author: &fixture.project.author,
+        cpr: template_answers,
+    })
+}
+
+/// Answers of `fixture` to `questions`, by key
+///
+/// Like interactive prompting, skipped questions are answered with `None`, within every `list`
+/// entry too. `prefix` locates the entry holding `answers` in errors.
+fn fixture_answers(
+    fixture: &Fixture,
+    questions: &[TemplateQuestion],
+    answers: &toml::Table,
+    prefix: &str,
+) -> miette::Result<BTreeMap<String, upon::Value>> {
+    let mut context = BTreeMap::new();
+    for question in questions {
+        let key = &question.key;
+        let answer = match (&question.kind, answers.get(key)) {
+            (QuestionKind::License, Some(answer)) => {
+                match answer.as_str().filter(|id| license::text(id).is_some()) {
+                    Some(id) => upon::Value::String(id.to_string()),
+                    None => {
+                        return Err(TemplateConfigError::FixtureLicense(
+                            fixture.name.clone(),
+                            format!("{prefix}{key}"),
+                            answer
+                                .as_str()
+                                .map_or_else(|| answer.to_string(), String::from),
+                        ))
+                        .into_diagnostic();
+                    }
+                }
+            }
+            (QuestionKind::List { questions }, Some(toml::Value::Array(entries)))
+                if entries.iter().all(toml::Value::is_table) =>
+            {
+                let mut list = Vec::new();
+                for (index, entry) in entries.iter().enumerate() {
+                    let entry = entry.as_table().unwrap();
+                    let prefix = format!("{prefix}{key}[{index}].");
+                    list.push(upon::Value::Map(fixture_answers(
+                        fixture, questions, entry, &prefix,
+                    )?));
+                }
+                upon::Value::List(list)
+            }
+            (_, Some(answer)) => upon::to_value(answer).into_diagnostic()?,
+            // questions with a `when` condition or computed choices may be skipped
+            (kind, None)
+                if question.when.is_some()
+                    || matches!(kind.choices(), Some(Choices::Computed(_))) =>
+            {
+                upon::Value::None
+            }
+            (_, None) => {
+                return Err(TemplateConfigError::MissingAnswer(
+                    fixture.name.clone(),
+                    format!("{prefix}{key}"),
+                ))
+                .into_diagnostic();
+            }
+        };
+        context.insert(key.to_string(), answer);
+    }
+    Ok(context)
+}
+
+/// Create a starter template in `directory`, along with the snapshot of its fixture
+pub fn new_template(config: &Config, directory: &Path) -> miette::Result<()> {
+    if directory.read_dir().is_ok_and(|mut d| d.next().is_some()) {
+        return Err(ProjectInitError::ProjectDirNotEmpty).into_diagnostic();
+    }
+
+    for (path, contents) in scaffold::FILES {
+        let path = directory.join(path);
+        std::fs::create_dir_all(path.parent().unwrap())
+            .map_err(|_| ProjectInitError::ProjectDirCreateFail)
+            .into_diagnostic()?;
+        std::fs::write(&path, contents)
+            .map_err(|_| ProjectInitError::WriteFileFail(path.display().to_string()))
+            .into_diagnostic()?;
+    }
+    git::init_repository(directory)?;
+    test_template(config, directory, true)?;
+
+    println!("Template created in `{}`", directory.display());
+    Ok(())
+}
+
+/// Turn the project at `project` into a template, along with a fixture reproducing the project
+pub fn extract_template(
+    config: &Config,
+    project: &Path,
+    into: Option<PathBuf>,
+    name: &str,
+    author: Option<String>,
+    year: Option<i32>,
+) -> miette::Result<()> {
+    let directory = match into {
+        Some(directory) => directory,
+        None => {
+            // `.` and `..` have no file name, take the sibling of the directory they resolve to
+            let project = project.canonicalize().into_diagnostic()?;
+            let mut name = project
+                .file_name()
+                .map_or_else(|| "project".into(), |n| n.to_os_string());
+            name.push("-template");
+            project.with_file_name(name)
+        }
+    };
+    if directory.read_dir().is_ok_and(|mut d| d.next().is_some()) {
+        return Err(ProjectInitError::ProjectDirNotEmpty).into_diagnostic();
+    }
+
+    let replacements = Replacements::new(name, author.as_deref(), year)?;
+    let files = extract::extract(project, &directory, &replacements)?;
+
+    let manifest = format!(
+        "# Extracted from `{}` by `cpr template extract`. The project name, author and year were\n\
+         # replaced with `{{{{ project.name }}}}`, `{{{{ author }}}}` and `{{{{ year }}}}`, run\n\
+         # `cpr template lint` to check the result. Declare [[questions]] below to make more of the\n\
+         # project configurable, `cpr template new` shows an example of every question type.\n",
+        project.display()
+    );
+    std::fs::write(directory.join("cpr.toml"), manifest).into_diagnostic()?;
+
+    // the fixture renders the original project back
+    let mut fixture = Fixture {
+        name: "default".to_string(),
+        project: FixtureProject {
+            name: name.to_string(),
+            year,
+            ..FixtureProject::default()
+        },
+        answers: toml::Table::new(),
+    };
+    if let Some(author) = author {
+        fixture.project.author = author;
+    }
+    let fixture_path = directory
+        .join(snapshot::TESTS_DIR)
+        .join(format!("{}.toml", fixture.name));
+    std::fs::create_dir_all(fixture_path.parent().unwrap()).into_diagnostic()?;
+    std::fs::write(&fixture_path, toml::to_string(&fixture).into_diagnostic()?)
+        .into_diagnostic()?;
+    test_template(config, &directory, true)?;
+
+    println!(
+        "Extracted {} files into `{}`",
+        files.len(),
+        directory.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{Generator, GeneratorFile};
+
+    #[test]
+    fn gen_renders_answers_skipped_when_creating_the_project() {
+        let project = tempfile::tempdir().unwrap();
+        let header = "{% if cpr.namespace %}namespace {{ cpr.namespace }}\n{% endif %}\
+            {% for lib in cpr.libraries %}{{ lib.name }}: {{ lib.include_dir }}\n{% endfor %}";
+        let generator = Generator {
+            description: None,
+            questions: Vec::new(),
+            files: vec![GeneratorFile {
+                source: "header.hpp".to_string(),
+                path: "{{ gen.name | snake }}.hpp".to_string(),
+            }],
+        };
+        let files_dir = Generators::files_dir(project.path(), "header");
+        std::fs::create_dir_all(&files_dir).unwrap();
+        std::fs::write(files_dir.join("header.hpp"), header).unwrap();
+        Generators {
+            generators: BTreeMap::from([("header".to_string(), generator)]),
+        }
+        .write(project.path())
+        .unwrap();
+        let library =
+            |include_dir: upon::Value| upon::value! { name: "core", include_dir: include_dir };
+        project::write_answers(
+            project.path(),
+            &upon::value! {
+                cpr: {
+                    namespace: upon::Value::None,
+                    libraries: upon::Value::List(vec![
+                        library(upon::Value::from("include/core")),
+                        library(upon::Value::None),
+                    ]),
+                },
+            },
+        )
+        .unwrap();
+
+        gen(
+            project.path(),
+            Some("header".to_string()),
+            vec!["MySocket".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(project.path().join("my_socket.hpp")).unwrap(),
+            "core: include/core\ncore: \n"
+        );
+    }
+}
@@ -0,0 +1,843 @@
+use crate::config::Config;
+use crate::errors::{ProjectInitError, TemplateConfigError};
+use crate::format;
+use crate::git::clone_repository;
+use crate::license;
+use crate::manifest::{QuestionKind, TemplateManifest, TemplateQuestion};
+use crate::registry;
+use crate::repo::RepoPath;
+use crate::variables;
+use miette::IntoDiagnostic;
+use requestty::Question;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Top-level entries of a template that are never copied to the project
+pub const RESERVED: &[&str] = &["cpr.toml", "_partials", "_generators", "_tests"];
+
+/// A template checked out on disk along with its `cpr.toml`
+pub struct Template {
+    /// Root directory of the checked out template
+    pub root: PathBuf,
+    /// Parsed `cpr.toml`, empty if the template has none
+    pub manifest: TemplateManifest,
+}
+
+impl Template {
+    pub fn load(root: PathBuf) -> miette::Result<Self> {
+        let cpr_path = root.join("cpr.toml");
+        let manifest = if cpr_path.exists() {
+            let cpr = std::fs::read_to_string(&cpr_path)
+                .map_err(|_| TemplateConfigError::FileReadFail)
+                .into_diagnostic()?;
+            TemplateManifest::parse(&cpr)?
+        } else {
+            TemplateManifest::default()
+        };
+        Ok(Self { root, manifest })
+    }
+
+    /// Fail if this version of cpr is outside of the range the template at `source` supports
+    pub fn check_version(&self, source: &str) -> miette::Result<()> {
+        let info = &self.manifest.template;
+        let current = semver::Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+        match info.cpr_versions() {
+            Some(range) if !info.supports(&current) => {
+                Err(TemplateConfigError::IncompatibleVersion(
+                    source.to_string(),
+                    range,
+                    current.to_string(),
+                ))
+                .into_diagnostic()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Templates this one is built on, in the order they are rendered:
+    /// `extends` first, then every `[[layers]]` entry
+    pub fn bases(&self) -> Vec<String> {
+        self.manifest
+            .extends
+            .iter()
+            .chain(self.manifest.layers.iter().map(|layer| &layer.repo))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Fetch a template and every template it is built on into `staging`
+///
+/// Templates are returned in render order: base templates first and `repo_path` last, so that
+/// later templates overlay the files of earlier ones.
+pub fn fetch(repo_path: &str, config: &Config, staging: &Path) -> miette::Result<Vec<Template>> {
+    let mut templates = Vec::new();
+    let mut fetched = Vec::new();
+    fetch_into(
+        repo_path,
+        config,
+        staging,
+        &mut Vec::new(),
+        &mut fetched,
+        &mut templates,
+    )?;
+    Ok(templates)
+}
+
+/// Templates from the one `chain` reaches `repo_path` again with back to `repo_path`, if it does
+fn extends_cycle<'a>(chain: &'a [String], repo_path: &'a str) -> Option<Vec<&'a str>> {
+    let start = chain.iter().position(|r| r == repo_path)?;
+    let templates = chain[start..].iter().map(String::as_str);
+    Some(templates.chain(std::iter::once(repo_path)).collect())
+}
+
+/// Fetch the templates a local template is built on into `staging`
+///
+/// Templates are returned in render order, ending with `template` itself.
+pub fn with_bases(
+    template: Template,
+    config: &Config,
+    staging: &Path,
+) -> miette::Result<Vec<Template>> {
+    let mut templates = Vec::new();
+    let mut fetched = Vec::new();
+    template.check_version(&template.root.display().to_string())?;
+    for base in template.bases() {
+        fetch_into(
+            &base,
+            config,
+            staging,
+            &mut Vec::new(),
+            &mut fetched,
+            &mut templates,
+        )?;
+    }
+    templates.push(template);
+    Ok(templates)
+}
+
+fn fetch_into(
+    repo_path: &str,
+    config: &Config,
+    staging: &Path,
+    chain: &mut Vec<String>,
+    fetched: &mut Vec<String>,
+    templates: &mut Vec<Template>,
+) -> miette::Result<()> {
+    let repo_path = &registry::resolve(config, &config.resolve_alias(repo_path))?;
+    if let Some(cycle) = extends_cycle(chain, repo_path) {
+        return Err(TemplateConfigError::CyclicExtends(cycle.join(" -> "))).into_diagnostic();
+    }
+    // a template shared by several layers is only rendered once
+    if fetched.iter().any(|r| r == repo_path) {
+        return Ok(());
+    }
+
+    let template = checkout(repo_path, config, staging)?;
+    template.check_version(repo_path)?;
+
+    chain.push(repo_path.to_string());
+    for base in template.bases() {
+        fetch_into(&base, config, staging, chain, fetched, templates)?;
+    }
+    chain.pop();
+
+    fetched.push(repo_path.to_string());
+    templates.push(template);
+    Ok(())
+}
+
+/// Clone a single template into `staging`, without the templates it is built on
+pub fn checkout(repo_path: &str, config: &Config, staging: &Path) -> miette::Result<Template> {
+    // every clone gets its own numbered directory
+    let count = std::fs::read_dir(staging).into_diagnostic()?.count();
+    let directory = staging.join(count.to_string());
+    let path = RepoPath::parse(repo_path);
+    clone_repository(&directory, &path, config)?;
+    match path.subdir {
+        Some(subdir) => Template::load(directory.join(subdir)),
+        None => Template::load(directory),
+    }
+}
+
+/// Questions of every template, asked once per key
+///
+/// A question redefined by a later template replaces the earlier definition but keeps its position.
+pub fn merge_questions(templates: &[Template]) -> Vec<TemplateQuestion> {
+    let mut questions = Vec::<TemplateQuestion>::new();
+    for question in templates.iter().flat_map(|t| &t.manifest.questions) {
+        match questions.iter_mut().find(|q| q.key == question.key) {
+            Some(existing) => *existing = question.clone(),
+            None => questions.push(question.clone()),
+        }
+    }
+    questions
+}
+
+/// What to do when a rendered file already exists in the project
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Replace the existing file
+    Always,
+    /// Prompt before replacing the existing file
+    Ask,
+    /// Keep the existing file
+    Never,
+}
+
+/// Filter storing the value of the expression it ends, so that evaluated expressions keep their
+/// type
+const CAPTURE: &str = "cpr_capture";
+
+/// Evaluates expressions to values, e.g. `cpr.warnings` to a list
+pub struct Evaluator {
+    engine: upon::Engine<'static>,
+    captured: Arc<Mutex<upon::Value>>,
+}
+
+impl Evaluator {
+    pub fn new() -> Self {
+        let captured = Arc::new(Mutex::new(upon::Value::None));
+        let mut engine = engine();
+        let slot = captured.clone();
+        engine.add_filter(CAPTURE, move |value: &upon::Value| {
+            *slot.lock().unwrap() = value.clone();
+            String::new()
+        });
+        Self { engine, captured }
+    }
+
+    pub fn evaluate(&self, expr: &str, context: upon::Value) -> Result<upon::Value, upon::Error> {
+        let (source, evaluated) = expression_source(expr);
+        let rendered = self
+            .engine
+            .compile(source)?
+            .render(&self.engine, context)
+            .to_string()?;
+        Ok(match evaluated {
+            Evaluated::Condition => upon::Value::Bool(!rendered.is_empty()),
+            Evaluated::Text => upon::Value::String(rendered),
+            Evaluated::Captured => std::mem::take(&mut *self.captured.lock().unwrap()),
+        })
+    }
+
+    /// Whether a `when` condition holds
+    pub fn holds(&self, expr: &str, context: upon::Value) -> Result<bool, upon::Error> {
+        let rendered = self
+            .engine
+            .compile(condition_source(expr))?
+            .render(&self.engine, context)
+            .to_string()?;
+        Ok(!rendered.is_empty())
+    }
+}
+
+/// Where the value of an expression comes from once rendered
+pub enum Evaluated {
+    /// `not` is only allowed in conditions, which render `true` or nothing
+    Condition,
+    /// Formatters only write text, so an expression ending with one is a string
+    Text,
+    /// Anything else is stored by the capture filter
+    Captured,
+}
+
+/// Template evaluating an expression
+pub fn expression_source(expr: &str) -> (String, Evaluated) {
+    let formatted = expr
+        .rsplit('|')
+        .next()
+        .is_some_and(|last| FORMATTERS.contains(&last.trim()));
+    if expr.trim_start().starts_with("not ") {
+        (condition_source(expr), Evaluated::Condition)
+    } else if formatted {
+        (format!("{{{{ {} }}}}", expr), Evaluated::Text)
+    } else {
+        (
+            format!("{{{{ {} | {} }}}}", expr, CAPTURE),
+            Evaluated::Captured,
+        )
+    }
+}
+
+/// Answers to `password` questions, hidden wherever answers are shown
+#[derive(Debug, Default, Clone)]
+pub struct Secrets {
+    /// Keys of the secret answers under `cpr`
+    answers: SecretKeys,
+    /// Variables evaluated from a secret answer
+    variables: Vec<String>,
+    /// Secret answers, masked in error output
+    values: Vec<String>,
+}
+
+/// Text shown in place of a secret
+pub const MASK: &str = "********";
+
+/// Keys of the `password` questions among some questions
+#[derive(Debug, Default, Clone)]
+struct SecretKeys(BTreeMap<String, SecretKey>);
+
+#[derive(Debug, Clone)]
+enum SecretKey {
+    Password,
+    /// `list` question with secret answers in its entries
+    List(SecretKeys),
+}
+
+impl SecretKeys {
+    fn new(questions: &[TemplateQuestion]) -> Self {
+        let keys = questions.iter().filter_map(|q| match &q.kind {
+            QuestionKind::Password { .. } => Some((q.key.clone(), SecretKey::Password)),
+            QuestionKind::List { questions } if q.is_secret() => {
+                Some((q.key.clone(), SecretKey::List(Self::new(questions))))
+            }
+            _ => None,
+        });
+        Self(keys.collect())
+    }
+
+    /// Secret answers among `answers`
+    fn values(&self, answers: &BTreeMap<String, upon::Value>, values: &mut Vec<String>) {
+        for (key, secret) in &self.0 {
+            match (secret, answers.get(key)) {
+                (SecretKey::Password, Some(upon::Value::String(answer))) => {
+                    values.push(answer.clone())
+                }
+                (SecretKey::List(keys), Some(upon::Value::List(entries))) => {
+                    for entry in entries {
+                        if let upon::Value::Map(entry) = entry {
+                            keys.values(entry, values);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Remove the secret answers from `answers`
+    fn strip(&self, answers: &mut BTreeMap<String, upon::Value>) {
+        for (key, secret) in &self.0 {
+            match (secret, answers.get_mut(key)) {
+                (SecretKey::Password, _) => {
+                    answers.remove(key);
+                }
+                (SecretKey::List(keys), Some(upon::Value::List(entries))) => {
+                    for entry in entries {
+                        if let upon::Value::Map(entry) = entry {
+                            keys.strip(entry);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Secrets {
+    /// Answers held by `cpr` in `context` to the secret questions among `questions`
+    pub fn new(questions: &[TemplateQuestion], context: &upon::Value) -> Self {
+        let answers = SecretKeys::new(questions);
+        let mut values = Vec::new();
+        if let upon::Value::Map(context) = context {
+            if let Some(upon::Value::Map(cpr)) = context.get("cpr") {
+                answers.values(cpr, &mut values);
+            }
+        }
+        // a secret within another one is masked after it
+        values.retain(|value| !value.is_empty());
+        values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        Self {
+            answers,
+            variables: Vec::new(),
+            values,
+        }
+    }
+
+    /// Also hide the variables whose expression refers to a secret answer, directly or through
+    /// other variables
+    pub fn with_variables(mut self, variables: &BTreeMap<String, String>) -> Self {
+        let keys = self
+            .answers
+            .0
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        self.variables = variables::derived(variables, &keys);
+        self
+    }
+
+    /// Whether the variable `name` holds a secret
+    pub fn is_secret_variable(&self, name: &str) -> bool {
+        self.variables.iter().any(|v| v == name)
+    }
+
+    /// `text` with every occurrence of a secret replaced by the mask, even within a word
+    pub fn mask(&self, text: &str) -> String {
+        self.values
+            .iter()
+            .fold(text.to_string(), |text, secret| text.replace(secret, MASK))
+    }
+
+    /// Output of a template error
+    ///
+    /// With secrets in the context, the excerpt of the template is left out and only the masked
+    /// reason is kept, as the excerpt could echo a secret.
+    pub fn describe(&self, e: &dyn std::fmt::Display) -> String {
+        if self.values.is_empty() {
+            format!("{:#}", e)
+        } else {
+            self.mask(&e.to_string())
+        }
+    }
+
+    /// Report a template error, described as by [`Secrets::describe`]
+    pub fn report<T, E>(&self, result: Result<T, E>) -> miette::Result<T>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        result.or_else(|e| {
+            if self.values.is_empty() {
+                Err(e).into_diagnostic()
+            } else {
+                Err(miette::miette!("{}", self.describe(&e)))
+            }
+        })
+    }
+
+    /// `context` without the secret answers and the variables evaluated from them, so that they
+    /// are never written to disk
+    pub fn strip(&self, context: &upon::Value) -> upon::Value {
+        let mut context = match context {
+            upon::Value::Map(context) => context.clone(),
+            context => return context.clone(),
+        };
+        if let Some(upon::Value::Map(cpr)) = context.get_mut("cpr") {
+            self.answers.strip(cpr);
+        }
+        if let Some(upon::Value::Map(vars)) = context.get_mut(variables::ROOT) {
+            vars.retain(|name, _| !self.is_secret_variable(name));
+        }
+        upon::Value::Map(context)
+    }
+}
+
+/// Renders template files into a project with a shared context
+pub struct Renderer {
+    engine: upon::Engine<'static>,
+    context: upon::Value,
+    /// Hidden from the errors of the renderer
+    secrets: Secrets,
+    // skip read errors if enabled
+    skip_all: bool,
+    overwrite: Overwrite,
+    /// Files written by this renderer
+    pub written: Vec<PathBuf>,
+    /// Files left untouched because they already existed
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Formatters available to template files, e.g. `{{ project.name | snake }}`
+pub const FORMATTERS: &[&str] = &[
+    "lower", "upper", "snake", "constant", "kebab", "pascal", "camel", "title",
+];
+
+/// Filters available to template files, e.g. `{{ cpr.license | spdx_header }}`
+pub const FILTERS: &[&str] = &["spdx_header"];
+
+/// Template engine with every formatter and filter registered
+pub fn engine() -> upon::Engine<'static> {
+    let mut engine = upon::Engine::new();
+    engine.add_formatter("lower", format::lower);
+    engine.add_formatter("upper", format::upper);
+    engine.add_formatter("snake", format::snake);
+    engine.add_formatter("constant", format::constant);
+    engine.add_formatter("kebab", format::kebab);
+    engine.add_formatter("pascal", format::pascal);
+    engine.add_formatter("camel", format::camel);
+    engine.add_formatter("title", format::title);
+    engine.add_filter("spdx_header", license::spdx_header);
+    engine
+}
+
+/// Prefix of the template a `when` expression is compiled into
+pub const CONDITION_PREFIX: &str = "{% if ";
+
+/// Template rendering `true` when the `when` expression of a question holds
+pub fn condition_source(expr: &str) -> String {
+    format!("{}{} %}}true{{% endif %}}", CONDITION_PREFIX, expr)
+}
+
+impl Renderer {
+    pub fn new(context: upon::Value) -> Self {
+        Self {
+            engine: engine(),
+            context,
+            secrets: Secrets::default(),
+            skip_all: false,
+            overwrite: Overwrite::Always,
+            written: Vec::new(),
+            skipped: Vec::new(),
+        }
+    }
+
+    pub fn with_overwrite(mut self, overwrite: Overwrite) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn with_secrets(mut self, secrets: Secrets) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    pub fn secrets(&self) -> &Secrets {
+        &self.secrets
+    }
+
+    /// Register partials so that any file can `{% include "name" %}` them
+    pub fn add_partials(&mut self, template: &Template) -> miette::Result<()> {
+        let partials_dir = template.root.join("_partials");
+        if !partials_dir.is_dir() {
+            return Ok(());
+        }
+
+        for entry in walkdir::WalkDir::new(&partials_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if entry.file_type().is_dir() {
+                continue;
+            }
+
+            let path = entry.into_path();
+            // `_partials/license/banner.txt` is included as "license/banner"
+            let name = path
+                .strip_prefix(&partials_dir)
+                .unwrap()
+                .with_extension("")
+                .to_string_lossy()
+                .replace('\\', "/");
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|_| {
+                    ProjectInitError::ReadFileFail(
+                        path.file_name().unwrap().to_str().unwrap().to_string(),
+                    )
+                })
+                .into_diagnostic()?;
+            log::debug!("registering partial `{}`", name);
+            self.engine.add_template(name, contents).into_diagnostic()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn context(&self) -> &upon::Value {
+        &self.context
+    }
+
+    /// Render a template source, compiled without a name so that it can't replace a partial
+    pub fn render_str(&self, source: &str) -> miette::Result<String> {
+        let template = self.engine.compile(source).into_diagnostic()?;
+        self.secrets.report(
+            template
+                .render_from(&self.engine, &self.context)
+                .to_string(),
+        )
+    }
+
+    /// Write a file into the project, honoring the overwrite policy for files that
+    /// existed before this renderer started
+    pub fn write(&mut self, path: &Path, contents: impl AsRef<[u8]>) -> miette::Result<()> {
+        if path.exists() && !self.written.iter().any(|p| p == path) {
+            let overwrite = match self.overwrite {
+                Overwrite::Always => true,
+                Overwrite::Never => false,
+                Overwrite::Ask => {
+                    let opt = Question::select("overwrite_policy")
+                        .message(format!(
+                            "`{}` already exists: What would you like to do?",
+                            path.display(),
+                        ))
+                        .choices(vec![
+                            "Skip this file".to_string(),
+                            "Overwrite this file".into(),
+                            "Skip all existing files".into(),
+                            "Overwrite all existing files".into(),
+                        ])
+                        .build();
+                    match requestty::prompt(vec![opt]).into_diagnostic()?["overwrite_policy"]
+                        .as_list_item()
+                        .unwrap()
+                        .text
+                        .as_str()
+                    {
+                        "Skip this file" => false,
+                        "Overwrite this file" => true,
+                        "Skip all existing files" => {
+                            self.overwrite = Overwrite::Never;
+                            false
+                        }
+                        "Overwrite all existing files" => {
+                            self.overwrite = Overwrite::Always;
+                            true
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+            };
+            if !overwrite {
+                log::warn!("Keeping existing file `{}`", path.display());
+                self.skipped.push(path.to_path_buf());
+                return Ok(());
+            }
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|_| ProjectInitError::ProjectDirCreateFail)
+                .into_diagnostic()?;
+        }
+        std::fs::write(path, contents)
+            .map_err(|_| {
+                ProjectInitError::WriteFileFail(
+                    path.file_name().unwrap().to_str().unwrap().to_string(),
+                )
+            })
+            .into_diagnostic()?;
+        if !self.written.iter().any(|p| p == path) {
+            self.written.push(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    /// Render the path of a template file, `None` if one of its components renders empty so that
+    /// files can be left out, e.g. `{% if cpr.tests %}tests{% endif %}/main.cpp`
+    pub fn render_path(&mut self, path: &Path) -> miette::Result<Option<PathBuf>> {
+        let mut rendered = PathBuf::new();
+        for component in path.iter() {
+            let component = component.to_string_lossy();
+            let component = if component.contains("{{") || component.contains("{%") {
+                self.render_str(&component)?
+            } else {
+                component.to_string()
+            };
+            if component.is_empty() {
+                return Ok(None);
+            }
+            // answers can't move files out of the project
+            for part in Path::new(&component).components() {
+                if let std::path::Component::Normal(part) = part {
+                    rendered.push(part);
+                }
+            }
+        }
+        Ok(Some(rendered))
+    }
+
+    /// Render every file of `template` into `directory`
+    pub fn render_into(&mut self, template: &Template, directory: &Path) -> miette::Result<()> {
+        let walker = walkdir::WalkDir::new(&template.root)
+            .sort_by_file_name()
+            .into_iter()
+            // a local template checkout keeps its repository
+            .filter_entry(|e| {
+                e.depth() != 1
+                    || (e.file_name() != ".git" && !RESERVED.iter().any(|r| e.file_name() == *r))
+            });
+
+        for entry in walker.filter_map(|e| e.ok()) {
+            if entry.file_type().is_dir() {
+                continue;
+            }
+
+            let source = entry.into_path();
+            let Some(path) = self.render_path(source.strip_prefix(&template.root).unwrap())? else {
+                log::debug!("leaving out `{}`", source.display());
+                continue;
+            };
+            let path = directory.join(path);
+
+            let contents = std::fs::read_to_string(&source).map_err(|_| {
+                ProjectInitError::ReadFileFail(
+                    path.file_name().unwrap().to_str().unwrap().to_string(),
+                )
+            });
+            // instead of returning an error, we can prompt the user to skip the file
+            // the options should be:
+            // 1. Skip this error
+            // 2. Skip all errors
+            // 3. Abort
+            // skipped files are copied as-is
+
+            if let Err(e) = contents {
+                if !self.skip_all {
+                    log::warn!("Failed to read file `{}`", path.display());
+
+                    let opt = Question::select("err_policy")
+                        .message(format!(
+                            "Failed to read file `{}`: What would you like to do?",
+                            path.file_name().unwrap().to_str().unwrap(),
+                        ))
+                        .choices(vec![
+                            "Skip this error".to_string(),
+                            "Skip all future errors".into(),
+                            "Abort".into(),
+                        ])
+                        .build();
+                    match requestty::prompt(vec![opt]).into_diagnostic()?["err_policy"]
+                        .as_list_item()
+                        .unwrap()
+                        .text
+                        .as_str()
+                    {
+                        "Skip this error" => {}
+                        "Skip all future errors" => self.skip_all = true,
+                        "Abort" => return Err(e).into_diagnostic(),
+                        _ => unreachable!(),
+                    }
+                }
+
+                log::warn!("Skipping file `{}`", path.display());
+                let contents = std::fs::read(&source)
+                    .map_err(|_| {
+                        ProjectInitError::ReadFileFail(
+                            path.file_name().unwrap().to_str().unwrap().to_string(),
+                        )
+                    })
+                    .into_diagnostic()?;
+                self.write(&path, contents)?;
+                continue;
+            }
+
+            let result = self.render_str(&contents.unwrap())?;
+            self.write(&path, result)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn questions(input: &str) -> Vec<TemplateQuestion> {
+        TemplateManifest::parse(input).unwrap().questions
+    }
+
+    #[test]
+    fn extends_cycles_start_at_the_repeated_template() {
+        let chain = ["app", "lib", "base"].map(String::from);
+        assert_eq!(
+            extends_cycle(&chain, "lib"),
+            Some(vec!["lib", "base", "lib"])
+        );
+        assert_eq!(extends_cycle(&chain[..1], "app"), Some(vec!["app", "app"]));
+        assert_eq!(extends_cycle(&chain, "other"), None);
+    }
+
+    /// Secrets of a context whose `token` answer is `secret`
+    fn token_secret(secret: &str) -> Secrets {
+        let questions = questions(
+            r#"
+            [[questions]]
+            key = "token"
+            message = "Token?"
+            type = "password"
+            "#,
+        );
+        Secrets::new(&questions, &upon::value! { cpr: { token: secret } })
+    }
+
+    #[test]
+    fn masks_every_occurrence_of_secrets() {
+        let secrets = token_secret("s3cret");
+        assert_eq!(secrets.mask("token s3cret."), "token ********.");
+        assert_eq!(
+            secrets.mask("s3crets and xs3cret"),
+            "********s and x********"
+        );
+        assert_eq!(token_secret("e").mask("some e"), "som******** ********");
+    }
+
+    #[test]
+    fn masks_secrets_in_render_errors() {
+        let source = "{{ cpr.token | spdx_header }}";
+        let context = upon::value! { cpr: { token: "s3cret" } };
+        let renderer = Renderer::new(context.clone()).with_secrets(token_secret("s3cret"));
+        assert_eq!(
+            renderer.render_str(source).unwrap_err().to_string(),
+            "filter error: unknown SPDX license identifier `********`"
+        );
+
+        let error = Renderer::new(context).render_str(source).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "filter error: unknown SPDX license identifier `s3cret`"
+        );
+    }
+
+    #[test]
+    fn strips_secrets_by_key() {
+        let questions = questions(
+            r#"
+            [[questions]]
+            key = "ns"
+            message = "Namespace?"
+            type = "input"
+
+            [[questions]]
+            key = "token"
+            message = "Token?"
+            type = "password"
+
+            [[questions]]
+            key = "services"
+            message = "Add a service?"
+            type = "list"
+
+            [[questions.questions]]
+            key = "name"
+            message = "Name?"
+            type = "input"
+
+            [[questions.questions]]
+            key = "key"
+            message = "Key?"
+            type = "password"
+            "#,
+        );
+        let context = upon::value! {
+            project: { name: "e" },
+            author: "e",
+            cpr: {
+                ns: "e",
+                token: "e",
+                services: [{ name: "api", key: "hidden" }],
+            },
+            vars: { bearer: "Bearer e", upper: "E" },
+        };
+        let variables = BTreeMap::from([
+            ("bearer".to_string(), "cpr.token | upper".to_string()),
+            ("upper".to_string(), "cpr.ns | upper".to_string()),
+        ]);
+        let secrets = Secrets::new(&questions, &context).with_variables(&variables);
+        let stripped = secrets.strip(&context);
+        assert_eq!(
+            stripped,
+            upon::value! {
+                project: { name: "e" },
+                author: "e",
+                cpr: {
+                    ns: "e",
+                    services: [{ name: "api" }],
+                },
+                vars: { upper: "E" },
+            }
+        );
+    }
+}
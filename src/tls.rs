@@ -0,0 +1,333 @@
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long the handshake reading the intermediates of a server may wait on the network
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+/// CA certificates trusted for the connections to a single service
+///
+/// libgit2 only adds CA certificates to the store shared by every connection of the process, so
+/// a bundle loaded there would be trusted by every later clone too. A bundle is checked from the
+/// certificate callback of its service instead.
+pub struct CaBundle {
+    verifier: Arc<WebPkiServerVerifier>,
+}
+
+impl CaBundle {
+    /// Read the PEM certificates of the file at `path`
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let pem = std::fs::read(path).map_err(|e| e.to_string())?;
+        Self::from_pem(&pem)
+    }
+
+    pub fn from_pem(pem: &[u8]) -> Result<Self, String> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(pem) {
+            let cert = cert.map_err(|e| e.to_string())?;
+            roots.add(cert).map_err(|e| e.to_string())?;
+        }
+        if roots.is_empty() {
+            return Err("no PEM certificate found".to_string());
+        }
+        let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider())
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self { verifier })
+    }
+
+    /// Whether the DER certificate presented by `host` chains up to the bundle through
+    /// `intermediates` and is issued for `host`
+    pub fn verifies(&self, der: &[u8], intermediates: &[CertificateDer], host: &str) -> bool {
+        let Ok(name) = ServerName::try_from(host) else {
+            return false;
+        };
+        self.verifier
+            .verify_server_cert(
+                &CertificateDer::from(der),
+                intermediates,
+                &name,
+                &[],
+                UnixTime::now(),
+            )
+            .is_ok()
+    }
+}
+
+/// Intermediate certificates presented by the TLS server at `host:port`, reached through the
+/// HTTP `proxy` if any
+///
+/// libgit2 only hands the leaf certificate to the certificate callback, so the rest of the chain
+/// is read from a handshake of its own. The certificates are not trusted by this handshake,
+/// they are only candidates for [`CaBundle::verifies`].
+pub fn presented_intermediates(
+    host: &str,
+    port: u16,
+    proxy: Option<&str>,
+) -> io::Result<Vec<CertificateDer<'static>>> {
+    let mut stream = match proxy {
+        Some(proxy) => connect_through(proxy, host, port)?,
+        None => TcpStream::connect((host, port))?,
+    };
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let recorder = Arc::new(ChainRecorder {
+        intermediates: Mutex::default(),
+        provider: provider(),
+    });
+    let config = ClientConfig::builder_with_provider(recorder.provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(recorder.clone())
+        .with_no_client_auth();
+    let name = ServerName::try_from(host.to_string()).map_err(io::Error::other)?;
+    let mut connection = ClientConnection::new(Arc::new(config), name).map_err(io::Error::other)?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream)?;
+    }
+    let intermediates = recorder.intermediates.lock().unwrap().take();
+    intermediates.ok_or_else(|| io::Error::other("the server presented no certificate"))
+}
+
+/// Open a tunnel to `host:port` with an HTTP `CONNECT` request to `proxy`
+fn connect_through(proxy: &str, host: &str, port: u16) -> io::Result<TcpStream> {
+    let url = url::Url::parse(proxy).map_err(io::Error::other)?;
+    let addresses = url.socket_addrs(|| Some(1080))?;
+    let mut stream = TcpStream::connect(&*addresses)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n\r\n"
+    )?;
+
+    // read the response byte by byte so that nothing of the tunnel is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        let mut byte = [0];
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        response.push(byte[0]);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(io::Error::other(format!("proxy answered `{}`", status)));
+    }
+    Ok(stream)
+}
+
+/// Keeps the intermediates the server presents and accepts its certificate, whose trust is
+/// decided by [`CaBundle::verifies`] afterwards
+#[derive(Debug)]
+struct ChainRecorder {
+    intermediates: Mutex<Option<Vec<CertificateDer<'static>>>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for ChainRecorder {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let intermediates = intermediates.iter().map(|c| c.clone().into_owned());
+        *self.intermediates.lock().unwrap() = Some(intermediates.collect());
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::PrivateKeyDer;
+    use rustls::{ServerConfig, ServerConnection};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const CA: &[u8] = include_bytes!("../testdata/tls/ca.pem");
+    /// Issued by `CA` for `git.internal` and `127.0.0.1`
+    const LEAF: &[u8] = include_bytes!("../testdata/tls/leaf.der");
+    /// Issued for the same names by another CA
+    const UNTRUSTED_LEAF: &[u8] = include_bytes!("../testdata/tls/untrusted-leaf.der");
+    /// Issued by `CA` for signing `CHAINED_LEAF`
+    const INTERMEDIATE: &[u8] = include_bytes!("../testdata/tls/intermediate.der");
+    /// Issued by `INTERMEDIATE` for the same names as `LEAF`
+    const CHAINED_LEAF: &[u8] = include_bytes!("../testdata/tls/chained-leaf.der");
+    const CHAINED_LEAF_KEY: &[u8] = include_bytes!("../testdata/tls/chained-leaf.key");
+
+    /// Serve `CHAINED_LEAF` along with `INTERMEDIATE` for a single handshake
+    fn serve_chain() -> (u16, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let chain = vec![
+            CertificateDer::from(CHAINED_LEAF),
+            CertificateDer::from(INTERMEDIATE),
+        ];
+        let key = PrivateKeyDer::from_pem_slice(CHAINED_LEAF_KEY).unwrap();
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+            let mut connection = ServerConnection::new(Arc::new(config)).unwrap();
+            while connection.is_handshaking() {
+                if connection.complete_io(&mut stream).is_err() {
+                    break;
+                }
+            }
+        });
+        (port, server)
+    }
+
+    /// Read the head of the request a proxy receives
+    fn read_request(client: &mut TcpStream) -> Vec<u8> {
+        let mut request = Vec::new();
+        while !request.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            client.read_exact(&mut byte).unwrap();
+            request.push(byte[0]);
+        }
+        request
+    }
+
+    #[test]
+    fn verifies_certificates_issued_by_the_bundle() {
+        let bundle = CaBundle::from_pem(CA).unwrap();
+        assert!(bundle.verifies(LEAF, &[], "git.internal"));
+        assert!(bundle.verifies(LEAF, &[], "127.0.0.1"));
+    }
+
+    #[test]
+    fn verifies_certificates_through_intermediates() {
+        let bundle = CaBundle::from_pem(CA).unwrap();
+        let intermediates = [CertificateDer::from(INTERMEDIATE)];
+        assert!(!bundle.verifies(CHAINED_LEAF, &[], "git.internal"));
+        assert!(bundle.verifies(CHAINED_LEAF, &intermediates, "git.internal"));
+    }
+
+    #[test]
+    fn rejects_other_hosts() {
+        let bundle = CaBundle::from_pem(CA).unwrap();
+        assert!(!bundle.verifies(LEAF, &[], "github.com"));
+        assert!(!bundle.verifies(LEAF, &[], "127.0.0.2"));
+    }
+
+    #[test]
+    fn rejects_certificates_of_other_issuers() {
+        let bundle = CaBundle::from_pem(CA).unwrap();
+        let intermediates = [CertificateDer::from(INTERMEDIATE)];
+        assert!(!bundle.verifies(UNTRUSTED_LEAF, &intermediates, "git.internal"));
+        assert!(!bundle.verifies(b"not a certificate", &[], "git.internal"));
+    }
+
+    #[test]
+    fn rejects_bundles_without_certificates() {
+        assert!(CaBundle::from_pem(b"").is_err());
+        assert!(CaBundle::from_pem(LEAF).is_err());
+    }
+
+    #[test]
+    fn reads_the_intermediates_presented_by_the_server() {
+        let (port, server) = serve_chain();
+        let intermediates = presented_intermediates("127.0.0.1", port, None).unwrap();
+        server.join().unwrap();
+        assert_eq!(intermediates, [CertificateDer::from(INTERMEDIATE)]);
+    }
+
+    #[test]
+    fn reads_the_intermediates_through_a_proxy() {
+        let (port, server) = serve_chain();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        let tunnel = thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let request = read_request(&mut client);
+            let target = format!("CONNECT 127.0.0.1:{} HTTP/1.1\r\n", port);
+            assert!(request.starts_with(target.as_bytes()));
+            let mut upstream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            client
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .unwrap();
+            let (mut client_read, mut upstream_write) =
+                (client.try_clone().unwrap(), upstream.try_clone().unwrap());
+            let forward = thread::spawn(move || io::copy(&mut client_read, &mut upstream_write));
+            let _ = io::copy(&mut upstream, &mut client);
+            let _ = forward.join();
+        });
+        let intermediates = presented_intermediates("127.0.0.1", port, Some(&proxy)).unwrap();
+        server.join().unwrap();
+        drop(tunnel);
+        assert_eq!(intermediates, [CertificateDer::from(INTERMEDIATE)]);
+    }
+
+    #[test]
+    fn reports_a_refusing_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            read_request(&mut client);
+            client.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").unwrap();
+        });
+        let error = presented_intermediates("git.internal", 443, Some(&proxy)).unwrap_err();
+        assert_eq!(error.to_string(), "proxy answered `HTTP/1.1 403 Forbidden`");
+    }
+}
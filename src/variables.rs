@@ -0,0 +1,183 @@
+use crate::errors::TemplateConfigError;
+use crate::template::{Evaluator, Secrets, Template, MASK};
+use miette::IntoDiagnostic;
+use std::collections::BTreeMap;
+
+/// Root under which variables are available to template files
+pub const ROOT: &str = "vars";
+
+/// `[variables]` of every template
+///
+/// A variable redefined by a later template replaces the earlier definition.
+pub fn merge(templates: &[Template]) -> BTreeMap<String, String> {
+    templates
+        .iter()
+        .flat_map(|t| t.manifest.variables.clone())
+        .collect()
+}
+
+/// Names of the variables an expression refers to as `vars.<name>`
+pub fn references(expr: &str) -> Vec<&str> {
+    let pattern = regex::Regex::new(r"\bvars\s*\.\s*([A-Za-z_][A-Za-z0-9_]*)").unwrap();
+    pattern
+        .captures_iter(expr)
+        .map(|c| c.get(1).unwrap().as_str())
+        .collect()
+}
+
+/// Variables whose expression refers to one of the answers `cpr.<key>` named by `keys`,
+/// directly or through other variables
+pub fn derived(variables: &BTreeMap<String, String>, keys: &[&str]) -> Vec<String> {
+    let answer = regex::Regex::new(r"\bcpr\s*\.\s*([A-Za-z_][A-Za-z0-9_]*)").unwrap();
+    let mut derived = Vec::<String>::new();
+    // variables come after the ones they refer to, cycles fail to evaluate anyway
+    for name in order(variables).unwrap_or_default() {
+        let expr = &variables[name];
+        let from_answer = answer
+            .captures_iter(expr)
+            .any(|c| keys.contains(&c.get(1).unwrap().as_str()));
+        let from_variable = references(expr)
+            .iter()
+            .any(|r| derived.iter().any(|d| d == r));
+        if from_answer || from_variable {
+            derived.push(name.to_string());
+        }
+    }
+    derived
+}
+
+/// Variables ordered so that every variable comes after the ones it refers to
+///
+/// Fails with the chain of names if variables refer to each other, e.g. `["a", "b", "a"]`.
+pub fn order(variables: &BTreeMap<String, String>) -> Result<Vec<&str>, Vec<String>> {
+    fn visit<'a>(
+        name: &'a str,
+        variables: &'a BTreeMap<String, String>,
+        chain: &mut Vec<&'a str>,
+        ordered: &mut Vec<&'a str>,
+    ) -> Result<(), Vec<String>> {
+        if let Some(start) = chain.iter().position(|n| *n == name) {
+            let mut cycle = chain[start..]
+                .iter()
+                .map(|n| n.to_string())
+                .collect::<Vec<_>>();
+            cycle.push(name.to_string());
+            return Err(cycle);
+        }
+        if ordered.contains(&name) {
+            return Ok(());
+        }
+        chain.push(name);
+        for reference in references(&variables[name]) {
+            // unknown variables fail when the expression is evaluated
+            if let Some((reference, _)) = variables.get_key_value(reference) {
+                visit(reference, variables, chain, ordered)?;
+            }
+        }
+        chain.pop();
+        ordered.push(name);
+        Ok(())
+    }
+
+    let mut ordered = Vec::with_capacity(variables.len());
+    for name in variables.keys() {
+        visit(name, variables, &mut Vec::new(), &mut ordered)?;
+    }
+    Ok(ordered)
+}
+
+/// Evaluate every variable with `context`, returning the map to add under `vars`
+pub fn evaluate(
+    variables: &BTreeMap<String, String>,
+    context: &upon::Value,
+    secrets: &Secrets,
+) -> miette::Result<upon::Value> {
+    let order = order(variables)
+        .map_err(|cycle| TemplateConfigError::CyclicVariables(cycle.join(" -> ")))
+        .into_diagnostic()?;
+
+    let evaluator = Evaluator::new();
+    let mut vars = BTreeMap::new();
+    for name in order {
+        let expr = &variables[name];
+        let mut context = match context {
+            upon::Value::Map(context) => context.clone(),
+            _ => BTreeMap::new(),
+        };
+        context.insert(ROOT.to_string(), upon::Value::Map(vars.clone()));
+
+        let value = evaluator
+            .evaluate(expr, upon::Value::Map(context))
+            .map_err(|e| TemplateConfigError::VariableFail(name.to_string(), secrets.describe(&e)))
+            .into_diagnostic()?;
+        if secrets.is_secret_variable(name) {
+            log::debug!("evaluated variable `{}` to {}", name, MASK);
+        } else {
+            log::debug!("evaluated variable `{}` to {:?}", name, value);
+        }
+        vars.insert(name.to_string(), value);
+    }
+    Ok(upon::Value::Map(vars))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definitions(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, expr)| (name.to_string(), expr.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_references_to_variables_only() {
+        assert_eq!(
+            references("vars.a | upper ~ vars . b ~ myvars.c ~ cpr.d"),
+            ["a", "b"]
+        );
+    }
+
+    #[test]
+    fn orders_variables_after_their_references() {
+        let variables = definitions(&[
+            ("a", "vars.c"),
+            ("b", "project.name"),
+            ("c", "vars.b"),
+            ("d", "vars.unknown"),
+        ]);
+        assert_eq!(order(&variables), Ok(vec!["b", "c", "a", "d"]));
+    }
+
+    #[test]
+    fn reports_the_chain_of_a_cycle() {
+        let variables = definitions(&[("a", "vars.b"), ("b", "vars.c"), ("c", "vars.a")]);
+        assert_eq!(order(&variables).unwrap_err(), ["a", "b", "c", "a"]);
+
+        let variables = definitions(&[("ok", "cpr.name"), ("self", "vars.self | upper")]);
+        assert_eq!(order(&variables).unwrap_err(), ["self", "self"]);
+    }
+
+    #[test]
+    fn derives_variables_from_answers_through_other_variables() {
+        let variables = definitions(&[
+            ("auth", "vars.header"),
+            ("header", "cpr.token | upper"),
+            ("name", "project.name"),
+        ]);
+        assert_eq!(derived(&variables, &["token"]), ["header", "auth"]);
+        assert!(derived(&variables, &["other"]).is_empty());
+    }
+
+    #[test]
+    fn evaluates_variables_in_order() {
+        let variables = definitions(&[("a", "vars.b | upper"), ("b", "project.name")]);
+        let context = upon::value! { project: { name: "demo" } };
+        let secrets = Secrets::new(&[], &context);
+        assert_eq!(
+            evaluate(&variables, &context, &secrets).unwrap(),
+            upon::value! { a: "DEMO", b: "demo" }
+        );
+    }
+}